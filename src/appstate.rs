@@ -1,15 +1,30 @@
 use crate::{
+    batch::{BatchAction, BatchPlan},
+    dataset::Dataset,
+    folder_index::FolderIndex,
+    image_filter::{ImageFilter, ImageSearch},
+    job::Job,
+    lint::{is_tiny, TinyBox},
+    lock::{HeldLock, LabelLock},
+    progress::ProgressCounter,
+    project::ProjectSettings,
+    query::{DatasetQuery, QueryRun},
     scrubber::Scrubber,
     settings::PersistentSettings,
+    thumbnails::Thumbnails,
+    tracks::{Keyframe, RewrittenLabels, TrackSpan},
     utils::{ExtendedImageInfo, Frame, Player},
+    yolo_labels::Label,
 };
 use image::RgbaImage;
 use nalgebra::Vector2;
 use notan::graphics::color::Color;
 use notan::{egui::epaint::ahash::HashMap, prelude::Texture, AppState};
 use std::{
+    collections::BTreeSet,
     path::PathBuf,
     sync::mpsc::{self, Receiver, Sender},
+    time::{Instant, SystemTime},
 };
 
 #[derive(Debug, Clone)]
@@ -48,6 +63,12 @@ pub struct AnnoationBoundingBox {
     width: f32,
     height: f32,
     pub class_id: u32,
+    pub probability: Option<f32>,
+    pub object_id: Option<u32>,
+    /// Generated by keyframe interpolation and not touched by the user since
+    pub interpolated: bool,
+    /// Hidden in the canvas. This is not saved.
+    pub hidden: bool,
 }
 
 impl Default for AnnoationBoundingBox {
@@ -58,6 +79,10 @@ impl Default for AnnoationBoundingBox {
             width: f32::NAN,
             height: f32::NAN,
             class_id: 0,
+            probability: None,
+            object_id: None,
+            interpolated: false,
+            hidden: false,
         }
     }
 }
@@ -76,6 +101,23 @@ impl AnnoationBoundingBox {
             width: width,
             height: height,
             class_id: class_id,
+            ..Default::default()
+        }
+    }
+
+    /// Create a box from an unnormalised label
+    pub fn from_label(label: &Label) -> AnnoationBoundingBox {
+        AnnoationBoundingBox {
+            probability: label.probability,
+            object_id: label.object_id,
+            interpolated: label.interpolated,
+            ..AnnoationBoundingBox::from_center(
+                label.x_centre,
+                label.y_centre,
+                label.width,
+                label.height,
+                label.label_index,
+            )
         }
     }
 
@@ -145,15 +187,32 @@ impl AnnoationBoundingBox {
         dx.abs() <= self.width / 2.0 && dy.abs() <= self.height / 2.0
     }
 
-    pub fn to_yolo_label_str(self, image_width: u32, image_height: u32) -> String {
-        format!(
-            "{} {} {} {} {}",
-            self.class_id,
-            self.center().x / (image_width as f32),
-            self.center().y / (image_height as f32),
-            self.size().0 / (image_width as f32),
-            self.size().1 / (image_height as f32),
-        )
+    /// Intersection over union with another box
+    pub fn iou(self, other: AnnoationBoundingBox) -> f32 {
+        let overlap_x =
+            (self.x_max().min(other.x_max()) - self.x_min().max(other.x_min())).max(0.0);
+        let overlap_y =
+            (self.y_max().min(other.y_max()) - self.y_min().max(other.y_min())).max(0.0);
+        let intersection = overlap_x * overlap_y;
+        let union = self.width * self.height + other.width * other.height - intersection;
+        if union > 0.0 {
+            intersection / union
+        } else {
+            0.0
+        }
+    }
+
+    pub fn to_label(self, image_width: u32, image_height: u32) -> Label {
+        Label {
+            label_index: self.class_id,
+            x_centre: self.center().x / (image_width as f32),
+            y_centre: self.center().y / (image_height as f32),
+            width: self.size().0 / (image_width as f32),
+            height: self.size().1 / (image_height as f32),
+            probability: self.probability,
+            object_id: self.object_id,
+            interpolated: self.interpolated,
+        }
     }
 
     pub fn set_corner_points(self: &mut Self, p1: Vector2<f32>, p2: Vector2<f32>) {
@@ -163,6 +222,31 @@ impl AnnoationBoundingBox {
         self.height = (p1.y - p2.y).abs();
     }
 
+    /// Move all corners to the nearest whole pixel
+    pub fn round_to_pixels(self: &mut Self) {
+        let tl = self.tl_corner().map(f32::round);
+        let br = self.br_corner().map(f32::round);
+        self.set_corner_points(tl, br);
+    }
+
+    /// Move the box inside an image of the given size, shrinking it if it is larger
+    pub fn clamp_to_image(&mut self, image_width: f32, image_height: f32) {
+        self.width = self.width.min(image_width);
+        self.height = self.height.min(image_height);
+        self.x_center = self
+            .x_center
+            .clamp(self.width / 2.0, image_width - self.width / 2.0);
+        self.y_center = self
+            .y_center
+            .clamp(self.height / 2.0, image_height - self.height / 2.0);
+    }
+
+    /// Make the box at least `min_size` wide and high, keeping its centre
+    pub fn grow_to(&mut self, min_size: f32) {
+        self.width = self.width.max(min_size);
+        self.height = self.height.max(min_size);
+    }
+
     pub fn set_center(self: &mut Self, p_center: Vector2<f32>) {
         self.x_center = p_center.x;
         self.y_center = p_center.y;
@@ -294,12 +378,245 @@ pub struct BoundingBoxElement {
     pub part: BoundingBoxPart,
 }
 
+/// Modifiers which change the shape of a box while it is drawn
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawModifiers {
+    /// The start point is the centre of the box instead of a corner
+    pub from_center: bool,
+    /// Width divided by height of the box, if it should be kept fixed
+    pub aspect_ratio: Option<f32>,
+}
+
+impl DrawModifiers {
+    /// The two corners of a box drawn from `start_point` to `cursor_position`
+    pub fn corner_points(
+        self,
+        start_point: Vector2<f32>,
+        cursor_position: Vector2<f32>,
+    ) -> (Vector2<f32>, Vector2<f32>) {
+        let mut delta = cursor_position - start_point;
+
+        if let Some(aspect_ratio) = self.aspect_ratio.filter(|a| *a > 0.0) {
+            let width = delta.x.abs().max(delta.y.abs() * aspect_ratio);
+            delta = Vector2::new(
+                width * delta.x.signum(),
+                width / aspect_ratio * delta.y.signum(),
+            );
+        }
+
+        if self.from_center {
+            (start_point - delta, start_point + delta)
+        } else {
+            (start_point, start_point + delta)
+        }
+    }
+}
+
+/// Lines the edges of a box snap to while it is edited
+#[derive(Clone, Debug, Default)]
+pub struct SnapTargets {
+    /// Vertical lines, by x position
+    pub xs: Vec<f32>,
+    /// Horizontal lines, by y position
+    pub ys: Vec<f32>,
+    /// How close an edge has to be to snap, in image pixels
+    pub distance: f32,
+}
+
+/// The lines an edit snapped to, drawn as guides
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SnapGuides {
+    pub x: Option<f32>,
+    pub y: Option<f32>,
+}
+
+/// The line closest to `value`, if it is within `distance`
+fn nearest_line(lines: &[f32], value: f32, distance: f32) -> Option<f32> {
+    lines
+        .iter()
+        .cloned()
+        .filter(|line| (line - value).abs() <= distance)
+        .min_by(|a, b| (a - value).abs().total_cmp(&(b - value).abs()))
+}
+
+/// The shortest move of either `low` or `high` onto a line, and that line
+fn nearest_span_offset(lines: &[f32], low: f32, high: f32, distance: f32) -> Option<(f32, f32)> {
+    [low, high]
+        .iter()
+        .cloned()
+        .filter_map(|edge| nearest_line(lines, edge, distance).map(|line| (line - edge, line)))
+        .min_by(|a, b| a.0.abs().total_cmp(&b.0.abs()))
+}
+
+impl SnapTargets {
+    /// Snap a dragged point to the nearest lines
+    pub fn snap_point(&self, p: Vector2<f32>) -> (Vector2<f32>, SnapGuides) {
+        let guides = SnapGuides {
+            x: nearest_line(&self.xs, p.x, self.distance),
+            y: nearest_line(&self.ys, p.y, self.distance),
+        };
+        (
+            Vector2::new(guides.x.unwrap_or(p.x), guides.y.unwrap_or(p.y)),
+            guides,
+        )
+    }
+
+    /// The offset which moves a whole box so one of its edges lies on a line
+    pub fn snap_box(&self, bbox: &AnnoationBoundingBox) -> (Vector2<f32>, SnapGuides) {
+        let x = nearest_span_offset(&self.xs, bbox.x_min(), bbox.x_max(), self.distance);
+        let y = nearest_span_offset(&self.ys, bbox.y_min(), bbox.y_max(), self.distance);
+        (
+            Vector2::new(
+                x.map(|(offset, _)| offset).unwrap_or_default(),
+                y.map(|(offset, _)| offset).unwrap_or_default(),
+            ),
+            SnapGuides {
+                x: x.map(|(_, line)| line),
+                y: y.map(|(_, line)| line),
+            },
+        )
+    }
+}
+
+/// How the boxes of one class are shown
+#[derive(Clone, Copy, Debug)]
+pub struct ClassDisplay {
+    pub hidden: bool,
+    pub opacity: f32,
+    /// Locked boxes can not be selected or edited in the image
+    pub locked: bool,
+}
+
+impl Default for ClassDisplay {
+    fn default() -> Self {
+        ClassDisplay {
+            hidden: false,
+            opacity: 1.0,
+            locked: false,
+        }
+    }
+}
+
+/// Which boxes are shown and which can be edited
+#[derive(Clone, Debug, Default)]
+pub struct AnnotationVisibility {
+    /// Hide all boxes to look at the raw image
+    pub hide_all: bool,
+    pub classes: HashMap<u32, ClassDisplay>,
+}
+
+impl AnnotationVisibility {
+    pub fn class(&self, class_id: u32) -> ClassDisplay {
+        self.classes.get(&class_id).cloned().unwrap_or_default()
+    }
+
+    pub fn class_mut(&mut self, class_id: u32) -> &mut ClassDisplay {
+        self.classes.entry(class_id).or_default()
+    }
+
+    pub fn is_visible(&self, bbox: &AnnoationBoundingBox) -> bool {
+        !self.hide_all && !bbox.hidden && !self.class(bbox.class_id).hidden
+    }
+
+    pub fn is_editable(&self, bbox: &AnnoationBoundingBox) -> bool {
+        self.is_visible(bbox) && !self.class(bbox.class_id).locked
+    }
+}
+
+/// What a left click in the image does
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tool {
+    /// Select, move and resize boxes, but never create one
+    Select,
+    /// Like select, but a click on empty space starts a new box
+    #[default]
+    Draw,
+    /// Place boxes of a fixed size
+    Stamp,
+    /// Drag the image around
+    Pan,
+}
+
+/// Menu for a box, opened by right clicking it
+#[derive(Clone, Copy, Debug)]
+pub struct AnnotationContextMenu {
+    pub bbox_id: usize,
+    /// Screen position of the click
+    pub position: Vector2<f32>,
+}
+
+/// A review walk waiting for the next image to load
+#[derive(Clone, Copy, Debug)]
+pub struct ReviewWalk {
+    pub forward: bool,
+    /// Images moved through so far, to stop if no image has boxes
+    pub images_visited: usize,
+}
+
+/// A column the annotation table can be sorted by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnnotationColumn {
+    #[default]
+    Index,
+    Class,
+    X,
+    Y,
+    Width,
+    Height,
+    Confidence,
+    Track,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AnnotationSort {
+    pub column: AnnotationColumn,
+    pub descending: bool,
+}
+
+impl AnnotationSort {
+    /// The annotation ids in table order
+    pub fn order(self, bboxes: &[AnnoationBoundingBox]) -> Vec<usize> {
+        let key = |id: usize| -> f32 {
+            let bbox = bboxes[id];
+            match self.column {
+                AnnotationColumn::Index => id as f32,
+                AnnotationColumn::Class => bbox.class_id as f32,
+                AnnotationColumn::X => bbox.x_min(),
+                AnnotationColumn::Y => bbox.y_min(),
+                AnnotationColumn::Width => bbox.width(),
+                AnnotationColumn::Height => bbox.height(),
+                AnnotationColumn::Confidence => bbox.probability.unwrap_or(1.0),
+                AnnotationColumn::Track => bbox.object_id.map(|id| id as f32).unwrap_or(-1.0),
+            }
+        };
+        let mut order: Vec<usize> = (0..bboxes.len()).collect();
+        // The sort is stable, so equal keys stay in drawing order
+        order.sort_by(|a, b| key(*a).total_cmp(&key(*b)));
+        if self.descending {
+            order.reverse();
+        }
+        order
+    }
+
+    /// Sort by `column`, or flip the direction if it is already sorted by it
+    pub fn toggle(&mut self, column: AnnotationColumn) {
+        if self.column == column {
+            self.descending = !self.descending;
+        } else {
+            self.column = column;
+            self.descending = false;
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum BoundingBoxEditMode {
     None,
     New {
         id: usize,
         start_point: Option<Vector2<f32>>,
+        /// In two-click mode, the box follows the cursor until the second click
+        awaiting_second_click: bool,
     },
     DragCorner {
         id: usize,
@@ -317,12 +634,28 @@ pub enum BoundingBoxEditMode {
 }
 
 impl BoundingBoxEditMode {
+    /// The box currently being edited
+    pub fn bbox_id(&self) -> Option<usize> {
+        match self {
+            BoundingBoxEditMode::None => None,
+            BoundingBoxEditMode::New { id, .. }
+            | BoundingBoxEditMode::DragCorner { id, .. }
+            | BoundingBoxEditMode::DragEdge { id, .. }
+            | BoundingBoxEditMode::DragFullBox { id, .. } => Some(*id),
+        }
+    }
+
     pub fn get_part_element(
         self: &Self,
         cursor_position: Vector2<f32>,
         annoation_bboxes: &Vec<AnnoationBoundingBox>,
+        visibility: &AnnotationVisibility,
     ) -> Option<BoundingBoxElement> {
-        for (id, bbox) in annoation_bboxes.iter().enumerate() {
+        // Boxes later in the list are drawn on top, so they are hit first
+        for (id, bbox) in annoation_bboxes.iter().enumerate().rev() {
+            if !visibility.is_editable(bbox) {
+                continue;
+            }
             if let Some(part) = bbox.get_part(cursor_position) {
                 return Some(BoundingBoxElement { id: id, part: part });
             }
@@ -338,14 +671,19 @@ impl BoundingBoxEditMode {
         cursor_position: Vector2<f32>,
         annoation_bboxes: &mut Vec<AnnoationBoundingBox>,
         selected_bbox_id: &mut Option<usize>,
-        label_class_id: u32,
+        // Class of a new box, or `None` if clicking empty space should not create one
+        label_class_id: Option<u32>,
+        two_click: bool,
+        visibility: &AnnotationVisibility,
     ) {
         *selected_bbox_id = None;
         match self {
             BoundingBoxEditMode::None => {
                 if let Some(clicked_part_element) =
-                    self.get_part_element(cursor_position, annoation_bboxes)
+                    self.get_part_element(cursor_position, annoation_bboxes, visibility)
                 {
+                    // Touching an interpolated box turns it into a reviewed keyframe
+                    annoation_bboxes[clicked_part_element.id].interpolated = false;
                     match clicked_part_element.part {
                         BoundingBoxPart::CentralArea => {
                             *self = BoundingBoxEditMode::DragFullBox {
@@ -406,20 +744,28 @@ impl BoundingBoxEditMode {
                 }
 
                 // Create new BoundingBox
+                let label_class_id = match label_class_id {
+                    Some(class_id) => class_id,
+                    None => return,
+                };
                 annoation_bboxes.push(AnnoationBoundingBox::default());
                 annoation_bboxes.last_mut().unwrap().class_id = label_class_id;
                 *self = BoundingBoxEditMode::New {
                     id: annoation_bboxes.len() - 1,
                     start_point: Some(cursor_position),
+                    awaiting_second_click: two_click,
                 };
             }
-            BoundingBoxEditMode::New { id, start_point } => {
-                if let Some(bbox) = annoation_bboxes.get_mut(*id) {
-                    if start_point.is_none() {
-                        *start_point = Some(cursor_position);
-                    } else {
-                        bbox.set_corner_points(start_point.unwrap(), cursor_position);
-                    }
+            BoundingBoxEditMode::New {
+                start_point,
+                awaiting_second_click,
+                ..
+            } => {
+                if start_point.is_none() {
+                    *start_point = Some(cursor_position);
+                } else {
+                    // The second click places the opposite corner, the box is done on release
+                    *awaiting_second_click = false;
                 }
             }
             BoundingBoxEditMode::DragCorner { .. } => {}
@@ -437,20 +783,30 @@ impl BoundingBoxEditMode {
         self: &mut Self,
         _cursor_position: Vector2<f32>,
         annoation_bboxes: &mut Vec<AnnoationBoundingBox>,
+        // New boxes narrower or lower than this are discarded, resized ones are grown back
+        min_size: f32,
+        image_dimension: (u32, u32),
     ) {
         match self {
             BoundingBoxEditMode::None => {}
+            BoundingBoxEditMode::New {
+                awaiting_second_click: true,
+                ..
+            } => {}
             BoundingBoxEditMode::New { id, .. } => {
-                if annoation_bboxes[*id].size().0 == 0.0 || annoation_bboxes[*id].size().1 == 0.0 {
+                let (width, height) = annoation_bboxes[*id].size();
+                if width == 0.0 || height == 0.0 || is_tiny(width, height, min_size) {
                     annoation_bboxes.remove(*id);
                 }
 
                 *self = BoundingBoxEditMode::None;
             }
-            BoundingBoxEditMode::DragCorner { .. } => {
-                *self = BoundingBoxEditMode::None;
-            }
-            BoundingBoxEditMode::DragEdge { .. } => {
+            BoundingBoxEditMode::DragCorner { id, .. }
+            | BoundingBoxEditMode::DragEdge { id, .. } => {
+                let bbox = &mut annoation_bboxes[*id];
+                bbox.grow_to(min_size);
+                // growing around the centre may push a box at the border out of the image
+                bbox.clamp_to_image(image_dimension.0 as f32, image_dimension.1 as f32);
                 *self = BoundingBoxEditMode::None;
             }
             BoundingBoxEditMode::DragFullBox { .. } => {
@@ -467,50 +823,80 @@ impl BoundingBoxEditMode {
         self: &mut Self,
         cursor_position: Vector2<f32>,
         annoation_bboxes: &mut Vec<AnnoationBoundingBox>,
-    ) {
+        draw_modifiers: DrawModifiers,
+        snap_targets: &SnapTargets,
+    ) -> SnapGuides {
+        let (snapped_cursor, cursor_guides) = snap_targets.snap_point(cursor_position);
         match self {
-            BoundingBoxEditMode::None => {}
-            BoundingBoxEditMode::New { id, start_point } => {
-                if let Some(bbox) = annoation_bboxes.get_mut(*id) {
-                    if start_point.is_some() {
-                        bbox.set_corner_points(start_point.unwrap(), cursor_position);
-                    }
+            BoundingBoxEditMode::None => SnapGuides::default(),
+            BoundingBoxEditMode::New {
+                id, start_point, ..
+            } => {
+                if let (Some(bbox), Some(start_point)) =
+                    (annoation_bboxes.get_mut(*id), start_point.as_mut())
+                {
+                    // The first corner snaps as well, so new boxes can share edges
+                    *start_point = snap_targets.snap_point(*start_point).0;
+                    let (p1, p2) = draw_modifiers.corner_points(*start_point, snapped_cursor);
+                    bbox.set_corner_points(p1, p2);
+                    cursor_guides
+                } else {
+                    SnapGuides::default()
                 }
             }
             BoundingBoxEditMode::DragCorner {
                 id,
                 static_opposite_point,
                 ..
-            } => annoation_bboxes
-                .get_mut(*id)
-                .unwrap()
-                .set_corner_points(*static_opposite_point, cursor_position),
+            } => {
+                annoation_bboxes
+                    .get_mut(*id)
+                    .unwrap()
+                    .set_corner_points(*static_opposite_point, snapped_cursor);
+                cursor_guides
+            }
             BoundingBoxEditMode::DragEdge { id, part } => match *part {
                 BoundingBoxPart::EdgeLeft => {
                     annoation_bboxes
                         .get_mut(*id)
                         .unwrap()
-                        .set_x_min(cursor_position.x);
+                        .set_x_min(snapped_cursor.x);
+                    SnapGuides {
+                        y: None,
+                        ..cursor_guides
+                    }
                 }
                 BoundingBoxPart::EdgeRight => {
                     annoation_bboxes
                         .get_mut(*id)
                         .unwrap()
-                        .set_x_max(cursor_position.x);
+                        .set_x_max(snapped_cursor.x);
+                    SnapGuides {
+                        y: None,
+                        ..cursor_guides
+                    }
                 }
                 BoundingBoxPart::EdgeTop => {
                     annoation_bboxes
                         .get_mut(*id)
                         .unwrap()
-                        .set_y_min(cursor_position.y);
+                        .set_y_min(snapped_cursor.y);
+                    SnapGuides {
+                        x: None,
+                        ..cursor_guides
+                    }
                 }
                 BoundingBoxPart::EdgeBottom => {
                     annoation_bboxes
                         .get_mut(*id)
                         .unwrap()
-                        .set_y_max(cursor_position.y);
+                        .set_y_max(snapped_cursor.y);
+                    SnapGuides {
+                        x: None,
+                        ..cursor_guides
+                    }
                 }
-                _ => {}
+                _ => SnapGuides::default(),
             },
             BoundingBoxEditMode::DragFullBox { id, offset } => {
                 let bbox = annoation_bboxes.get_mut(*id).unwrap();
@@ -519,6 +905,9 @@ impl BoundingBoxEditMode {
                     cursor_position.x - offset.x,
                     cursor_position.y - offset.y,
                 ));
+                let (snap_offset, guides) = snap_targets.snap_box(bbox);
+                bbox.set_center(bbox.center() + snap_offset);
+                guides
             }
         }
     }
@@ -579,6 +968,81 @@ pub struct OculanteState {
     pub current_bounding_box_element_under_cursor: Option<BoundingBoxElement>,
     pub current_label_class: u32,
     pub label_colors: Vec<Color>,
+    /// First keyframe of a box to interpolate from
+    pub keyframe: Option<Keyframe>,
+    /// All tracks of the image sequence. Collected on demand, as this reads all label files.
+    pub track_spans: Option<Vec<TrackSpan>>,
+    /// Collects `track_spans` in the background
+    pub track_spans_job: Option<Job<Vec<TrackSpan>>>,
+    /// A merge, split or renumbering of tracks running in the background
+    pub track_operation_job: Option<Job<anyhow::Result<RewrittenLabels>>>,
+    /// Settings of the dataset in the current folder
+    pub project: ProjectSettings,
+    /// The folder `project` was loaded from
+    pub project_folder: Option<PathBuf>,
+    /// The tool used for left clicks in the image
+    pub tool: Tool,
+    /// Scale applied to the stamp size
+    pub stamp_scale: f32,
+    /// Size of the last box drawn or edited, per class
+    pub remembered_box_sizes: HashMap<u32, (f32, f32)>,
+    /// Order of the annotation table
+    pub annotation_sort: AnnotationSort,
+    pub annotation_visibility: AnnotationVisibility,
+    /// Where the right mouse button went down, to tell clicks from panning
+    pub right_click_start: Option<Vector2<f32>>,
+    pub annotation_context_menu: Option<AnnotationContextMenu>,
+    pub review_walk: Option<ReviewWalk>,
+    pub snap_guides: SnapGuides,
+    /// Result of the last check of the sequence for tiny boxes
+    pub tiny_boxes: Option<Vec<TinyBox>>,
+    pub tiny_boxes_job: Option<Job<Vec<TinyBox>>>,
+    /// Images the scrub bar buttons skip to
+    pub image_filter: ImageFilter,
+    /// Looking for the next image matching a filter which needs the files
+    pub image_search_job: Option<Job<ImageSearch>>,
+    /// Query being typed in
+    pub query_text: String,
+    /// The applied query the image sequence is restricted to
+    pub dataset_query: Option<DatasetQuery>,
+    pub query_job: Option<Job<QueryRun>>,
+    /// Expression selecting the boxes of a batch operation
+    pub batch_expression: String,
+    pub batch_action: BatchAction,
+    /// Preview of the batch operation, applied when confirmed
+    pub batch_plan: Option<BatchPlan>,
+    /// Backup of the label files changed by the last batch operation
+    pub batch_backup: Option<PathBuf>,
+    pub thumbnails: Thumbnails,
+    /// Images selected in the thumbnail grid. They are kept by path, as the sequence can be
+    /// sorted or filtered while they are selected.
+    pub thumbnail_selection: BTreeSet<PathBuf>,
+    /// Where a shift click in the thumbnail grid extends the selection from
+    pub thumbnail_anchor: Option<PathBuf>,
+    /// Shuffle seed being dragged or typed in, which is applied when the edit is done
+    pub shuffle_seed: Option<u64>,
+    /// Images spanning several folders the scrub bar moves through
+    pub dataset: Option<Dataset>,
+    /// The images of the folder and their labels, read in the background
+    pub folder_index: Option<FolderIndex>,
+    /// How many images of the folder are annotated
+    pub progress: ProgressCounter,
+    /// The title last given to the window
+    pub window_title: String,
+    /// The annotations as the label file had them when it was last read or written
+    pub saved_annotations: Vec<Label>,
+    /// When the label file was last read or written
+    pub labels_modified: Option<SystemTime>,
+    /// When the label file was last checked for changes made by others
+    pub labels_checked: Option<Instant>,
+    /// Boxes of a label file changed on disk while there were unsaved edits
+    pub label_conflict: Option<Vec<AnnoationBoundingBox>>,
+    /// The image whose labels we hold the lock of
+    pub label_lock: Option<HeldLock>,
+    /// When the lock was last taken or tried
+    pub lock_refreshed: Option<Instant>,
+    /// Someone else's lock on the current image, which makes its labels read-only
+    pub locked_by: Option<LabelLock>,
 }
 
 impl OculanteState {
@@ -586,6 +1050,10 @@ impl OculanteState {
         _ = self.message_channel.0.send(Message::info(msg));
     }
 
+    pub fn send_message_warn(&self, msg: &str) {
+        _ = self.message_channel.0.send(Message::warn(msg));
+    }
+
     pub fn send_message_err(&self, msg: &str) {
         _ = self.message_channel.0.send(Message::err(msg));
     }
@@ -640,6 +1108,47 @@ impl Default for OculanteState {
             annotation_bboxes: vec![],
             current_bounding_box_element_under_cursor: None,
             current_label_class: 0,
+            keyframe: None,
+            track_spans: None,
+            track_spans_job: None,
+            track_operation_job: None,
+            project: Default::default(),
+            project_folder: None,
+            tool: Default::default(),
+            stamp_scale: 1.0,
+            remembered_box_sizes: Default::default(),
+            annotation_sort: Default::default(),
+            annotation_visibility: Default::default(),
+            right_click_start: None,
+            annotation_context_menu: None,
+            review_walk: None,
+            snap_guides: Default::default(),
+            tiny_boxes: None,
+            tiny_boxes_job: None,
+            image_filter: Default::default(),
+            image_search_job: None,
+            query_text: Default::default(),
+            dataset_query: None,
+            query_job: None,
+            batch_expression: Default::default(),
+            batch_action: Default::default(),
+            batch_plan: None,
+            batch_backup: None,
+            thumbnails: Default::default(),
+            thumbnail_selection: Default::default(),
+            thumbnail_anchor: None,
+            shuffle_seed: None,
+            dataset: None,
+            folder_index: None,
+            progress: Default::default(),
+            window_title: Default::default(),
+            saved_annotations: Default::default(),
+            labels_modified: None,
+            labels_checked: None,
+            label_conflict: None,
+            label_lock: None,
+            lock_refreshed: None,
+            locked_by: None,
             label_colors: vec![
                 Color {
                     r: 0.894,
@@ -0,0 +1,275 @@
+use crate::lock::locked_by_other;
+use crate::query::{box_context, image_size_for};
+use crate::tracks::read_labels_for_image;
+use crate::utils::{file_modified, labels_filename_for};
+use crate::yolo_labels::{tracks_filename_for, Label, Labels};
+use anyhow::{Context, Result};
+use evalexpr::eval_boolean_with_context;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Folder inside the project folder holding the label files replaced by batch operations
+pub const BACKUP_FOLDER: &str = ".oculante_backup";
+
+/// Lists which backup file belongs to which label file
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// What a batch operation does with the boxes matching its expression
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BatchAction {
+    #[default]
+    Delete,
+    SetClass(u32),
+    /// Grow width and height by a percentage, or shrink them if it is negative
+    Expand(f32),
+}
+
+impl BatchAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Delete => "Delete",
+            Self::SetClass(_) => "Set class",
+            Self::Expand(_) => "Expand",
+        }
+    }
+
+    /// The changed box, or `None` if it is deleted
+    pub fn apply(&self, label: &Label) -> Option<Label> {
+        let mut label = *label;
+        match self {
+            Self::Delete => return None,
+            Self::SetClass(class_id) => label.label_index = *class_id,
+            Self::Expand(percent) => {
+                // Grow around the centre, but stay inside the image
+                let factor = (1.0 + percent / 100.0).max(0.0);
+                let half_width = label.width * factor / 2.0;
+                let half_height = label.height * factor / 2.0;
+                let left = (label.x_centre - half_width).max(0.0);
+                let right = (label.x_centre + half_width).min(1.0);
+                let top = (label.y_centre - half_height).max(0.0);
+                let bottom = (label.y_centre + half_height).min(1.0);
+                label.x_centre = (left + right) / 2.0;
+                label.y_centre = (top + bottom) / 2.0;
+                label.width = (right - left).max(0.0);
+                label.height = (bottom - top).max(0.0);
+            }
+        }
+        Some(label)
+    }
+}
+
+/// The new labels of one image changed by a batch operation
+#[derive(Debug, Clone)]
+pub struct BatchChange {
+    pub image_path: PathBuf,
+    /// Number of boxes matching the expression
+    pub affected: usize,
+    pub labels: Vec<Label>,
+}
+
+/// A batch operation worked out for a sequence of images, but not written yet
+#[derive(Debug, Clone)]
+pub struct BatchPlan {
+    pub expression: String,
+    pub action: BatchAction,
+    pub changes: Vec<BatchChange>,
+}
+
+impl BatchPlan {
+    pub fn num_boxes(&self) -> usize {
+        self.changes.iter().map(|c| c.affected).sum()
+    }
+}
+
+/// Work out which boxes of `entries` match `expression` and what they become
+pub fn plan_batch(expression: &str, action: BatchAction, entries: &[PathBuf]) -> Result<BatchPlan> {
+    let mut changes = vec![];
+    for image_path in entries {
+        let labels = read_labels_for_image(image_path);
+        if labels.is_empty() {
+            continue;
+        }
+        let image_size = image_size_for(expression, image_path)
+            .with_context(|| format!("Can't get size of {}", image_path.display()))?;
+
+        let mut affected = 0;
+        let mut new_labels = vec![];
+        for label in labels.iter() {
+            let context = box_context(label, image_size)?;
+            let matches = eval_boolean_with_context(expression, &context)
+                .with_context(|| format!("Can't evaluate for {}", image_path.display()))?;
+            if matches {
+                affected += 1;
+                new_labels.extend(action.apply(label));
+            } else {
+                new_labels.push(*label);
+            }
+        }
+        if affected > 0 {
+            changes.push(BatchChange {
+                image_path: image_path.clone(),
+                affected,
+                labels: new_labels,
+            });
+        }
+    }
+    Ok(BatchPlan {
+        expression: expression.into(),
+        action,
+        changes,
+    })
+}
+
+/// The label file of an image before a batch operation changed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackupEntry {
+    labels_file: PathBuf,
+    backup_file: PathBuf,
+    /// The copy of the track file, if there was one
+    tracks_backup: Option<PathBuf>,
+    /// When the batch operation wrote the label file, to notice later edits
+    written: Option<SystemTime>,
+}
+
+impl BackupEntry {
+    /// Copy back the label and track files
+    fn restore(&self) -> std::io::Result<()> {
+        std::fs::copy(&self.backup_file, &self.labels_file)?;
+        let tracks_file = tracks_filename_for(&self.labels_file);
+        match &self.tracks_backup {
+            Some(tracks_backup) => std::fs::copy(tracks_backup, tracks_file).map(|_| ()),
+            None if tracks_file.exists() => std::fs::remove_file(tracks_file),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What `apply_batch` did
+#[derive(Debug, Clone)]
+pub struct AppliedBatch {
+    /// Where the replaced files are kept to revert the operation
+    pub backup_folder: PathBuf,
+    pub num_images: usize,
+    pub num_boxes: usize,
+    /// Images skipped because someone else is editing their labels
+    pub locked: Vec<PathBuf>,
+}
+
+/// What `revert_batch` did
+#[derive(Debug, Clone)]
+pub struct RevertedBatch {
+    pub restored: usize,
+    /// Label files kept because they were edited after the batch operation
+    pub edited: Vec<PathBuf>,
+}
+
+/// Write all label files of a plan, except the ones locked by other annotators. The previous
+/// files are copied to a new backup folder first, so the operation can be reverted.
+pub fn apply_batch(plan: &BatchPlan, project_folder: &Path) -> Result<AppliedBatch> {
+    let (changes, locked): (Vec<&BatchChange>, Vec<&BatchChange>) = plan
+        .changes
+        .iter()
+        .partition(|change| locked_by_other(&change.image_path).is_none());
+
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let backup_folder = project_folder.join(BACKUP_FOLDER).join(millis.to_string());
+    std::fs::create_dir_all(&backup_folder)?;
+
+    let mut manifest = vec![];
+    for (i, change) in changes.iter().enumerate() {
+        let labels_file = labels_filename_for(&change.image_path);
+        let backup_file = backup_folder.join(format!("{i}.txt"));
+        std::fs::copy(&labels_file, &backup_file)?;
+        // The track file lists the boxes line by line, so it changes with them
+        let tracks_file = tracks_filename_for(&labels_file);
+        let tracks_backup = if tracks_file.exists() {
+            let tracks_backup = backup_folder.join(format!("{i}.tracks"));
+            std::fs::copy(&tracks_file, &tracks_backup)?;
+            Some(tracks_backup)
+        } else {
+            None
+        };
+        manifest.push(BackupEntry {
+            labels_file,
+            backup_file,
+            tracks_backup,
+            written: None,
+        });
+    }
+
+    // Write all new files next to the old ones before replacing any, so a failure leaves
+    // the dataset as it was
+    let mut written = vec![];
+    for change in &changes {
+        let new_file = labels_filename_for(&change.image_path).with_extension("txt.batch");
+        let labels = Labels {
+            labels: change.labels.clone(),
+        };
+        if let Err(e) = std::fs::write(&new_file, labels.to_string()) {
+            _ = std::fs::remove_file(&new_file);
+            for (new_file, _) in &written {
+                _ = std::fs::remove_file(new_file);
+            }
+            _ = std::fs::remove_dir_all(&backup_folder);
+            return Err(e.into());
+        }
+        written.push((new_file, labels));
+    }
+    // Put back the files replaced so far and drop the ones not used yet
+    let roll_back = |manifest: &[BackupEntry], unused: &[(PathBuf, Labels)]| {
+        for entry in manifest {
+            _ = entry.restore();
+        }
+        for (new_file, _) in unused {
+            _ = std::fs::remove_file(new_file);
+        }
+        _ = std::fs::remove_dir_all(&backup_folder);
+    };
+    for (i, (entry, (new_file, labels))) in manifest.iter_mut().zip(&written).enumerate() {
+        let replaced = std::fs::rename(new_file, &entry.labels_file)
+            .and_then(|_| labels.write_tracks(&entry.labels_file));
+        if let Err(e) = replaced {
+            roll_back(&manifest[..=i], &written[i..]);
+            return Err(e.into());
+        }
+        entry.written = file_modified(&entry.labels_file);
+    }
+    let saved = File::create(backup_folder.join(MANIFEST_FILE))
+        .map_err(anyhow::Error::from)
+        .and_then(|f| serde_json::to_writer_pretty(f, &manifest).map_err(Into::into));
+    if let Err(e) = saved {
+        roll_back(&manifest, &[]);
+        return Err(e);
+    }
+
+    Ok(AppliedBatch {
+        backup_folder,
+        num_images: changes.len(),
+        num_boxes: changes.iter().map(|c| c.affected).sum(),
+        locked: locked.iter().map(|c| c.image_path.clone()).collect(),
+    })
+}
+
+/// Restore the label and track files saved by `apply_batch`. Label files edited after the
+/// batch operation are kept, so later work isn't lost.
+pub fn revert_batch(backup_folder: &Path) -> Result<RevertedBatch> {
+    let manifest: Vec<BackupEntry> =
+        serde_json::from_reader(File::open(backup_folder.join(MANIFEST_FILE))?)?;
+    let mut reverted = RevertedBatch {
+        restored: 0,
+        edited: vec![],
+    };
+    for entry in &manifest {
+        if file_modified(&entry.labels_file) != entry.written {
+            reverted.edited.push(entry.labels_file.clone());
+            continue;
+        }
+        entry.restore()?;
+        reverted.restored += 1;
+    }
+    Ok(reverted)
+}
@@ -0,0 +1,244 @@
+use crate::scrubber::get_image_filenames_recursive;
+use crate::utils::is_ext_compatible;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    path::{Path, PathBuf},
+};
+
+/// Splits of a `data.yaml` that can be opened
+pub const SPLITS: [&str; 3] = ["train", "val", "test"];
+
+/// Where the images of the scrub bar come from, instead of the folder of the current image
+#[derive(Debug, Clone, PartialEq)]
+pub enum DatasetSource {
+    /// All images in a folder and the folders below it
+    Recursive(PathBuf),
+    /// The images listed in a Darknet/Ultralytics `train.txt`, or in a split of a `data.yaml`
+    List {
+        file: PathBuf,
+        split: Option<String>,
+    },
+}
+
+impl DatasetSource {
+    /// A list file. For a `data.yaml`, the training split is used.
+    pub fn list(file: &Path) -> Self {
+        DatasetSource::List {
+            file: file.to_path_buf(),
+            split: is_yaml(file).then(|| SPLITS[0].to_string()),
+        }
+    }
+}
+
+/// A set of images spanning several folders
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    pub source: DatasetSource,
+    pub entries: Vec<PathBuf>,
+    /// Class names from a `data.yaml`
+    pub class_names: Vec<String>,
+}
+
+impl Dataset {
+    pub fn open(source: DatasetSource) -> Result<Self> {
+        let mut class_names = vec![];
+        let entries = match &source {
+            DatasetSource::Recursive(folder) => get_image_filenames_recursive(folder)?,
+            DatasetSource::List { file, split } => match split {
+                Some(split) => {
+                    let data = DataYaml::try_from(std::fs::read_to_string(file)?.as_str())
+                        .with_context(|| format!("Can't read {}", file.display()))?;
+                    class_names = data.names.clone();
+                    data.split_entries(file, split)?
+                }
+                None => read_image_list(file)?,
+            },
+        };
+        if entries.is_empty() {
+            bail!("No images found");
+        }
+        Ok(Self {
+            source,
+            entries,
+            class_names,
+        })
+    }
+
+    /// The folder the project file of the dataset is kept in
+    pub fn root(&self) -> PathBuf {
+        match &self.source {
+            DatasetSource::Recursive(folder) => folder.clone(),
+            DatasetSource::List { file, .. } => {
+                file.parent().map(|p| p.to_path_buf()).unwrap_or_default()
+            }
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match &self.source {
+            DatasetSource::Recursive(folder) => format!("{} and subfolders", folder.display()),
+            DatasetSource::List { file, split } => {
+                let name = file
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                match split {
+                    Some(split) => format!("{name} ({split})"),
+                    None => name,
+                }
+            }
+        }
+    }
+}
+
+fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("yaml") | Some("yml")
+    )
+}
+
+/// Whether a file may list the images of a dataset rather than be one
+pub fn is_dataset_list(path: &Path) -> bool {
+    is_yaml(path) || path.extension().and_then(|e| e.to_str()) == Some("txt")
+}
+
+/// Read a list file with one image path per line. Relative paths are relative to the list
+/// file, as in Ultralytics, or else to the working directory, as in Darknet.
+pub fn read_image_list(file: &Path) -> Result<Vec<PathBuf>> {
+    let folder = file.parent().unwrap_or_else(|| Path::new("."));
+    let entries: Vec<PathBuf> = std::fs::read_to_string(file)?
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let relative_to_list = folder.join(l);
+            if relative_to_list.exists() {
+                relative_to_list
+            } else {
+                PathBuf::from(l)
+            }
+        })
+        .filter(|p| is_ext_compatible(p))
+        .collect();
+    if entries.is_empty() {
+        bail!("{} lists no images", file.display());
+    }
+    Ok(entries)
+}
+
+/// The parts of an Ultralytics `data.yaml` needed to find the images of a split. Only the
+/// flat layout these files use is understood, not YAML in general. The labels of the images
+/// are found with `labels_filename_for`, which knows the `images/` and `labels/` folders.
+#[derive(Debug, Default, PartialEq)]
+pub struct DataYaml {
+    /// Folder the splits are relative to
+    pub path: Option<String>,
+    /// Image folders, list files or images of each split
+    pub splits: BTreeMap<String, Vec<String>>,
+    pub names: Vec<String>,
+}
+
+impl TryFrom<&str> for DataYaml {
+    type Error = anyhow::Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let unquote = |v: &str| v.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+        // Items of `[a, b]` or the values of `{0: a, 1: b}`
+        let inline_items = |v: &str| -> Result<Vec<String>> {
+            let inner = match v.strip_prefix('[') {
+                Some(list) => list.strip_suffix(']'),
+                None => v.strip_prefix('{').and_then(|map| map.strip_suffix('}')),
+            };
+            let inner = match inner {
+                Some(inner) => inner,
+                None => bail!("Unclosed bracket in {v}"),
+            };
+            Ok(inner
+                .split(',')
+                .map(|item| unquote(item.split_once(':').map(|(_, v)| v).unwrap_or(item)))
+                .filter(|item| !item.is_empty())
+                .collect())
+        };
+
+        let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut key = None;
+        for line in s.lines() {
+            let line = match line.find(" #") {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            // Indented lines and `- item` belong to the last key
+            if line.starts_with(char::is_whitespace) || trimmed.starts_with('-') {
+                let item = match trimmed.strip_prefix('-') {
+                    Some(item) => unquote(item),
+                    None => match trimmed.split_once(':') {
+                        Some((_, value)) => unquote(value),
+                        None => continue,
+                    },
+                };
+                if let Some(key) = &key {
+                    values.entry(String::from(key)).or_default().push(item);
+                }
+                continue;
+            }
+
+            if let Some((k, value)) = trimmed.split_once(':') {
+                let value = value.trim();
+                let items = if value.starts_with('[') || value.starts_with('{') {
+                    inline_items(value)?
+                } else if value.is_empty() {
+                    vec![]
+                } else {
+                    vec![unquote(value)]
+                };
+                values.insert(k.trim().to_string(), items);
+                key = Some(k.trim().to_string());
+            }
+        }
+
+        Ok(DataYaml {
+            path: values.get("path").and_then(|p| p.first().cloned()),
+            names: values.get("names").cloned().unwrap_or_default(),
+            splits: values
+                .into_iter()
+                .filter(|(k, _)| SPLITS.contains(&k.as_str()))
+                .collect(),
+        })
+    }
+}
+
+impl DataYaml {
+    /// The images of a split. Folders are searched recursively and `.txt` files are read
+    /// as lists.
+    pub fn split_entries(&self, yaml_file: &Path, split: &str) -> Result<Vec<PathBuf>> {
+        let folder = yaml_file.parent().unwrap_or_else(|| Path::new("."));
+        let base = match &self.path {
+            Some(path) => folder.join(path),
+            None => folder.to_path_buf(),
+        };
+        let mut entries = vec![];
+        for item in self
+            .splits
+            .get(split)
+            .with_context(|| format!("No split '{split}' in {}", yaml_file.display()))?
+        {
+            let path = base.join(item);
+            if path.is_dir() {
+                entries.extend(get_image_filenames_recursive(&path)?);
+            } else if is_ext_compatible(&path) {
+                entries.push(path);
+            } else {
+                entries.extend(read_image_list(&path)?);
+            }
+        }
+        Ok(entries)
+    }
+}
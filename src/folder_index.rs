@@ -0,0 +1,421 @@
+use crate::project::SortOrder;
+use crate::scrubber::get_image_filenames_for_directory;
+use crate::utils::{file_modified, is_ext_compatible, labels_filename_for};
+use crate::yolo_labels::{Label, Labels};
+use log::debug;
+use notify::{Config, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// Label files read before the ones found so far are sent to the UI
+const LABELS_PER_UPDATE: usize = 500;
+
+/// How often the folders are polled for changes where they can't be watched natively
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the watching thread waits for events before it checks whether to stop
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Events are collected this long before they are handled
+const EVENT_DELAY: Duration = Duration::from_millis(100);
+
+/// What the index keeps about the label file of an image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSummary {
+    /// Whether there is a label file at all
+    pub exists: bool,
+    pub boxes: usize,
+    /// Classes of the boxes, sorted and without repeats
+    pub classes: Vec<u32>,
+    /// The highest track id of the boxes
+    pub max_object_id: Option<u32>,
+}
+
+impl LabelSummary {
+    /// A label file with these labels
+    pub fn new(labels: &[Label]) -> Self {
+        let mut classes: Vec<u32> = labels.iter().map(|l| l.label_index).collect();
+        classes.sort_unstable();
+        classes.dedup();
+        Self {
+            exists: true,
+            boxes: labels.len(),
+            classes,
+            max_object_id: labels.iter().filter_map(|l| l.object_id).max(),
+        }
+    }
+
+    pub fn read(image_path: &Path) -> Self {
+        match Labels::from_file(labels_filename_for(image_path)) {
+            Ok(labels) => Self::new(&labels),
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    debug!("Can't read the labels of {}: {e}", image_path.display());
+                }
+                Self::default()
+            }
+        }
+    }
+}
+
+/// The images to index
+#[derive(Debug, Clone)]
+pub enum IndexSource {
+    /// The images of a folder, which are watched for new and removed files
+    Folder(PathBuf),
+    /// A fixed list of images, like a dataset
+    Entries(Vec<PathBuf>),
+}
+
+#[derive(Debug)]
+enum IndexUpdate {
+    /// The sorted images, replacing the ones sent before
+    Entries(Vec<PathBuf>),
+    Labels(Vec<(PathBuf, LabelSummary)>),
+    /// All label files have been read once
+    Ready,
+}
+
+/// What changed in the index since it was last polled
+#[derive(Debug, Default)]
+pub struct IndexChanges {
+    pub entries: bool,
+    /// Images whose label file changed on disk after the index was ready
+    pub labels: Vec<PathBuf>,
+}
+
+/// The images of a folder in the sort order of the project and a summary of their labels,
+/// read once in the background and then kept up to date by watching the folders for changes.
+#[derive(Debug)]
+pub struct FolderIndex {
+    /// The indexed folder, unless the index is for a fixed list of images
+    pub folder: Option<PathBuf>,
+    pub entries: Vec<PathBuf>,
+    pub labels: HashMap<PathBuf, LabelSummary>,
+    pub ready: bool,
+    updates: Receiver<IndexUpdate>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FolderIndex {
+    pub fn new(source: IndexSource, order: SortOrder) -> Self {
+        let (sender, updates) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let folder = match &source {
+            IndexSource::Folder(folder) => Some(folder.clone()),
+            IndexSource::Entries(_) => None,
+        };
+        let thread_stop = stop.clone();
+        std::thread::spawn(move || index_images(source, order, sender, thread_stop));
+        Self {
+            folder,
+            entries: vec![],
+            labels: Default::default(),
+            ready: false,
+            updates,
+            stop,
+        }
+    }
+
+    /// Take in what the indexing thread found
+    pub fn poll(&mut self) -> IndexChanges {
+        let mut changes = IndexChanges::default();
+        for update in self.updates.try_iter() {
+            match update {
+                IndexUpdate::Entries(entries) => {
+                    self.entries = entries;
+                    changes.entries = true;
+                }
+                IndexUpdate::Labels(labels) => {
+                    for (image_path, summary) in labels {
+                        if self.ready {
+                            changes.labels.push(image_path.clone());
+                        }
+                        self.labels.insert(image_path, summary);
+                    }
+                }
+                IndexUpdate::Ready => self.ready = true,
+            }
+        }
+        changes
+    }
+
+    /// Whether the index knows the image
+    pub fn covers(&self, image_path: &Path) -> bool {
+        self.entries.iter().any(|p| p == image_path)
+    }
+
+    /// Whether the index will know the image once the folder has been read
+    pub fn will_cover(&self, image_path: &Path) -> bool {
+        self.entries.is_empty()
+            && self.folder.is_some()
+            && self.folder.as_deref() == image_path.parent()
+    }
+
+    /// The labels of an image were saved
+    pub fn set_labels(&mut self, image_path: &Path, summary: LabelSummary) {
+        self.labels.insert(image_path.to_path_buf(), summary);
+    }
+}
+
+impl Drop for FolderIndex {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// When the label file of an image was written and how many boxes it has
+type LabelState = (Option<SystemTime>, usize);
+
+fn read_labels(
+    image_path: &Path,
+    known: &mut HashMap<PathBuf, LabelState>,
+) -> (PathBuf, LabelSummary) {
+    let summary = LabelSummary::read(image_path);
+    let time = file_modified(&labels_filename_for(image_path));
+    known.insert(image_path.to_path_buf(), (time, summary.boxes));
+    (image_path.to_path_buf(), summary)
+}
+
+/// The images in `order`, from the images in name order
+fn sort_entries(
+    names: &[PathBuf],
+    order: SortOrder,
+    known: &HashMap<PathBuf, LabelState>,
+) -> Vec<PathBuf> {
+    let mut entries = names.to_vec();
+    // The sorts are stable, so equal images stay in name order
+    match order {
+        SortOrder::Name => {}
+        SortOrder::Modified => entries.sort_by_cached_key(|p| file_modified(p)),
+        SortOrder::Size => entries
+            .sort_by_cached_key(|p| std::fs::metadata(p).map(|m| m.len()).unwrap_or_default()),
+        SortOrder::Boxes => entries.sort_by_key(|p| known.get(p).map(|k| k.1)),
+        SortOrder::LabelsEdited => entries.sort_by_key(|p| known.get(p).and_then(|k| k.0)),
+        SortOrder::Shuffle(seed) => entries.shuffle(&mut ChaCha8Rng::seed_from_u64(seed)),
+    }
+    entries
+}
+
+/// Read the images and their labels, then watch their folders until the index is dropped
+fn index_images(
+    source: IndexSource,
+    order: SortOrder,
+    sender: Sender<IndexUpdate>,
+    stop: Arc<AtomicBool>,
+) {
+    let folder = match &source {
+        IndexSource::Folder(folder) => Some(folder.clone()),
+        IndexSource::Entries(_) => None,
+    };
+    let mut names = match source {
+        IndexSource::Folder(folder) => {
+            get_image_filenames_for_directory(&folder).unwrap_or_default()
+        }
+        IndexSource::Entries(entries) => entries,
+    };
+    // The images can be moved through while their labels are read
+    if sender.send(IndexUpdate::Entries(names.clone())).is_err() {
+        return;
+    }
+
+    let mut known = HashMap::new();
+    for chunk in names.chunks(LABELS_PER_UPDATE) {
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let labels = chunk.iter().map(|p| read_labels(p, &mut known)).collect();
+        if sender.send(IndexUpdate::Labels(labels)).is_err() {
+            return;
+        }
+    }
+    let mut entries = sort_entries(&names, order, &known);
+    if order != SortOrder::Name && sender.send(IndexUpdate::Entries(entries.clone())).is_err() {
+        return;
+    }
+    if sender.send(IndexUpdate::Ready).is_err() {
+        return;
+    }
+
+    let (event_sender, events) = channel();
+    let mut watcher = FolderWatcher::new(event_sender);
+    let mut label_images = HashMap::new();
+    if let Some(folder) = &folder {
+        watcher.watch(folder);
+    }
+    watch_labels(&names, &mut watcher, &mut label_images);
+
+    while !stop.load(Ordering::Relaxed) {
+        let first = match events.recv_timeout(STOP_CHECK_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        // Files are often written in several steps, so the events of a moment are taken together
+        std::thread::sleep(EVENT_DELAY);
+        let mut images_changed = false;
+        let mut edited = HashSet::new();
+        for event in std::iter::once(first).chain(events.try_iter()) {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    debug!("Watching failed: {e}");
+                    continue;
+                }
+            };
+            for path in event.paths {
+                if let Some(image_path) = label_images.get(&path) {
+                    edited.insert(image_path.clone());
+                } else if folder.as_deref() == path.parent() && is_ext_compatible(&path) {
+                    images_changed = true;
+                }
+            }
+        }
+
+        if let Some(folder) = folder.as_ref().filter(|_| images_changed) {
+            let new_names = get_image_filenames_for_directory(folder).unwrap_or_default();
+            if new_names != names {
+                debug!("Images of {} changed", folder.display());
+                let added: Vec<PathBuf> = new_names
+                    .iter()
+                    .filter(|p| !known.contains_key(*p))
+                    .cloned()
+                    .collect();
+                let labels: Vec<(PathBuf, LabelSummary)> =
+                    added.iter().map(|p| read_labels(p, &mut known)).collect();
+                let present: HashSet<&PathBuf> = new_names.iter().collect();
+                known.retain(|p, _| present.contains(p));
+                label_images.retain(|_, p| present.contains(p));
+                watch_labels(&added, &mut watcher, &mut label_images);
+                entries = update_entries(&entries, &new_names, &added, order, &known);
+                names = new_names;
+                if sender.send(IndexUpdate::Entries(entries.clone())).is_err() {
+                    return;
+                }
+                if !labels.is_empty() && sender.send(IndexUpdate::Labels(labels)).is_err() {
+                    return;
+                }
+            }
+        }
+
+        // Events also come for files written with the same contents, or by the index itself
+        let edited: Vec<PathBuf> = edited
+            .into_iter()
+            .filter(|p| known.get(p).map(|k| k.0) != Some(file_modified(&labels_filename_for(p))))
+            .collect();
+        if edited.is_empty() {
+            continue;
+        }
+        debug!("Label files changed: {:?}", edited);
+        let labels: Vec<(PathBuf, LabelSummary)> =
+            edited.iter().map(|p| read_labels(p, &mut known)).collect();
+        if sender.send(IndexUpdate::Labels(labels)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Watches folders for changes, natively where the system supports it and by polling
+/// otherwise, like on some network shares
+struct FolderWatcher {
+    watcher: Option<Box<dyn Watcher + Send>>,
+    sender: Sender<notify::Result<notify::Event>>,
+    watched: HashSet<PathBuf>,
+    polling: bool,
+}
+
+impl FolderWatcher {
+    fn new(sender: Sender<notify::Result<notify::Event>>) -> Self {
+        let watcher = RecommendedWatcher::new(sender.clone(), Config::default())
+            .map(|w| Box::new(w) as Box<dyn Watcher + Send>)
+            .map_err(|e| debug!("Can't watch natively: {e}"))
+            .ok();
+        let mut watcher = Self {
+            watcher,
+            sender,
+            watched: HashSet::new(),
+            polling: false,
+        };
+        if watcher.watcher.is_none() {
+            watcher.poll();
+        }
+        watcher
+    }
+
+    /// Switch to polling, which works wherever the files can be read
+    fn poll(&mut self) {
+        let config = Config::default().with_poll_interval(WATCH_INTERVAL);
+        self.watcher = PollWatcher::new(self.sender.clone(), config)
+            .map(|w| Box::new(w) as Box<dyn Watcher + Send>)
+            .map_err(|e| debug!("Can't poll: {e}"))
+            .ok();
+        self.polling = true;
+        for folder in self.watched.clone() {
+            self.watch_once(&folder);
+        }
+    }
+
+    fn watch_once(&mut self, folder: &Path) -> bool {
+        match &mut self.watcher {
+            Some(watcher) => watcher.watch(folder, RecursiveMode::NonRecursive).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Watch the files of a folder, but not its subfolders
+    fn watch(&mut self, folder: &Path) {
+        if !self.watched.insert(folder.to_path_buf()) {
+            return;
+        }
+        if !self.watch_once(folder) && !self.polling {
+            debug!("Polling {} instead", folder.display());
+            self.poll();
+        }
+    }
+}
+
+/// Watch the folders of the label files of images, and remember which image each belongs to
+fn watch_labels(
+    images: &[PathBuf],
+    watcher: &mut FolderWatcher,
+    label_images: &mut HashMap<PathBuf, PathBuf>,
+) {
+    for image_path in images {
+        let labels_file = labels_filename_for(image_path);
+        if let Some(folder) = labels_file.parent() {
+            watcher.watch(folder);
+        }
+        label_images.insert(labels_file, image_path.clone());
+    }
+}
+
+/// The sorted images after images were added or removed. The images which were there keep
+/// their place, so the sequence doesn't jump around while it is annotated.
+fn update_entries(
+    entries: &[PathBuf],
+    names: &[PathBuf],
+    added: &[PathBuf],
+    order: SortOrder,
+    known: &HashMap<PathBuf, LabelState>,
+) -> Vec<PathBuf> {
+    if order == SortOrder::Name {
+        return names.to_vec();
+    }
+    let mut updated: Vec<PathBuf> = entries
+        .iter()
+        .filter(|p| known.contains_key(*p))
+        .cloned()
+        .collect();
+    updated.extend(sort_entries(added, order, known));
+    updated
+}
@@ -0,0 +1,121 @@
+use crate::folder_index::LabelSummary;
+use crate::lint::find_tiny_boxes_in_image;
+use crate::lock::locked_by_other;
+use crate::project::ProjectSettings;
+use crate::tracks::read_labels_for_image;
+use crate::utils::labels_filename_for;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Which images to stop at when skipping through a folder
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFilter {
+    #[default]
+    All,
+    /// Images without a label file, which have never been annotated
+    Unannotated,
+    /// Images with a label file that contains no boxes
+    EmptyLabels,
+    /// Images with at least one box of a class
+    WithClass(u32),
+    /// Images the linter reports boxes for
+    Flagged,
+    /// Images nobody else has locked for editing
+    Unlocked,
+}
+
+impl ImageFilter {
+    pub fn matches(self, image_path: &Path, min_box_size: f32) -> bool {
+        match self {
+            ImageFilter::All => true,
+            ImageFilter::Unannotated => !labels_filename_for(image_path).exists(),
+            ImageFilter::EmptyLabels => {
+                labels_filename_for(image_path).exists()
+                    && read_labels_for_image(image_path).is_empty()
+            }
+            ImageFilter::WithClass(class_id) => read_labels_for_image(image_path)
+                .iter()
+                .any(|l| l.label_index == class_id),
+            ImageFilter::Flagged => {
+                !find_tiny_boxes_in_image(0, image_path, min_box_size).is_empty()
+            }
+            ImageFilter::Unlocked => locked_by_other(image_path).is_none(),
+        }
+    }
+
+    /// Whether the summary of a label file matches, if that is enough to tell
+    pub fn matches_summary(self, summary: &LabelSummary) -> Option<bool> {
+        match self {
+            ImageFilter::All => Some(true),
+            ImageFilter::Unannotated => Some(!summary.exists),
+            ImageFilter::EmptyLabels => Some(summary.exists && summary.boxes == 0),
+            ImageFilter::WithClass(class_id) => Some(summary.classes.contains(&class_id)),
+            // Box sizes need the image
+            ImageFilter::Flagged => None,
+            ImageFilter::Unlocked => None,
+        }
+    }
+
+    /// Whether the filter reads files for every image, which is too slow for the UI thread
+    pub fn reads_files(self) -> bool {
+        matches!(self, ImageFilter::Flagged | ImageFilter::Unlocked)
+    }
+
+    pub fn name(self, project: &ProjectSettings) -> String {
+        match self {
+            ImageFilter::All => "All images".into(),
+            ImageFilter::Unannotated => "Unannotated".into(),
+            ImageFilter::EmptyLabels => "Empty labels".into(),
+            ImageFilter::WithClass(class_id) => format!("Class {}", project.class_name(class_id)),
+            ImageFilter::Flagged => "Flagged by lint".into(),
+            ImageFilter::Unlocked => "Not locked by others".into(),
+        }
+    }
+}
+
+/// The next image matching a filter, looked for in the background
+#[derive(Debug, Clone)]
+pub struct ImageSearch {
+    pub filter: ImageFilter,
+    /// The image the search started from
+    pub from: PathBuf,
+    pub found: Option<PathBuf>,
+}
+
+/// Index of the next image from `index` in the given direction that matches the filter.
+/// The current image is only returned if `wrap` is set and no other image matches.
+/// Label files are only read for images without a summary in `labels`.
+pub fn find_image(
+    entries: &[PathBuf],
+    labels: Option<&HashMap<PathBuf, LabelSummary>>,
+    index: usize,
+    forward: bool,
+    wrap: bool,
+    filter: ImageFilter,
+    min_box_size: f32,
+) -> Option<usize> {
+    let len = entries.len();
+    let candidates: Vec<usize> = if wrap {
+        (1..=len)
+            .map(|step| {
+                if forward {
+                    (index + step) % len
+                } else {
+                    (index + len - step % len) % len
+                }
+            })
+            .collect()
+    } else if forward {
+        (index + 1..len).collect()
+    } else {
+        (0..index.min(len)).rev().collect()
+    };
+    candidates.into_iter().find(|i| {
+        labels
+            .and_then(|l| l.get(&entries[*i]))
+            .and_then(|summary| filter.matches_summary(summary))
+            .unwrap_or_else(|| filter.matches(&entries[*i], min_box_size))
+    })
+}
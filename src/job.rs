@@ -0,0 +1,25 @@
+use std::sync::mpsc::{channel, Receiver};
+
+/// Work done on another thread, so the UI doesn't wait for it
+#[derive(Debug)]
+pub struct Job<T> {
+    result: Receiver<T>,
+}
+
+impl<T: Send + 'static> Job<T> {
+    pub fn spawn<F>(work: F) -> Self
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (sender, result) = channel();
+        std::thread::spawn(move || {
+            _ = sender.send(work());
+        });
+        Self { result }
+    }
+
+    /// The result, once the work is done. It is only returned once.
+    pub fn poll(&self) -> Option<T> {
+        self.result.try_recv().ok()
+    }
+}
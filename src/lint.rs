@@ -0,0 +1,61 @@
+use crate::tracks::read_labels_for_image;
+use log::debug;
+use std::path::{Path, PathBuf};
+
+/// A box smaller than the minimum size, usually left behind by a jittery click
+#[derive(Debug, Clone)]
+pub struct TinyBox {
+    /// Index of the image in the sequence
+    pub image_index: usize,
+    /// Index of the box within the labels of that image
+    pub bbox_id: usize,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Whether a box is too small to be a deliberate annotation
+pub fn is_tiny(width: f32, height: f32, min_size: f32) -> bool {
+    width < min_size || height < min_size
+}
+
+/// Find the boxes smaller than `min_size` image pixels in the label file of one image
+pub fn find_tiny_boxes_in_image(
+    image_index: usize,
+    image_path: &Path,
+    min_size: f32,
+) -> Vec<TinyBox> {
+    let labels = read_labels_for_image(image_path);
+    if labels.is_empty() {
+        return vec![];
+    }
+    // Only the header is read to get the size
+    let (image_width, image_height) = match image::image_dimensions(image_path) {
+        Ok(dimensions) => dimensions,
+        Err(e) => {
+            debug!("Can't get size of {}: {e}", image_path.display());
+            return vec![];
+        }
+    };
+    labels
+        .iter()
+        .enumerate()
+        .map(|(bbox_id, label)| TinyBox {
+            image_index,
+            bbox_id,
+            width: label.width * image_width as f32,
+            height: label.height * image_height as f32,
+        })
+        .filter(|tiny_box| is_tiny(tiny_box.width, tiny_box.height, min_size))
+        .collect()
+}
+
+/// Find the boxes smaller than `min_size` image pixels in the label files of the sequence
+pub fn find_tiny_boxes(entries: &[PathBuf], min_size: f32) -> Vec<TinyBox> {
+    entries
+        .iter()
+        .enumerate()
+        .flat_map(|(image_index, image_path)| {
+            find_tiny_boxes_in_image(image_index, image_path, min_size)
+        })
+        .collect()
+}
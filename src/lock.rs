@@ -0,0 +1,232 @@
+use crate::utils::labels_filename_for;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Locks older than this were left behind, for example by a crash, and are ignored
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// How often a held lock is renewed so it doesn't expire while the image is open
+pub const LOCK_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Who is editing the labels of an image. Locks are advisory: they are only respected by
+/// other annotators using them too.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelLock {
+    pub user: String,
+    pub host: String,
+    /// Seconds since the Unix epoch when the lock was taken or last renewed
+    pub time: u64,
+    /// Which running instance took the lock, as one user may open an image twice
+    #[serde(default)]
+    pub session: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".into())
+}
+
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+/// Identifies this process among the instances of the same user on the same computer
+fn session_id() -> &'static str {
+    static SESSION: OnceLock<String> = OnceLock::new();
+    SESSION.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        format!("{}-{started}", std::process::id())
+    })
+}
+
+impl LabelLock {
+    /// A lock held by this user on this computer, taken now
+    pub fn new() -> Self {
+        Self {
+            user: user_name(),
+            host: host_name(),
+            time: now(),
+            session: session_id().into(),
+        }
+    }
+
+    pub fn is_mine(&self) -> bool {
+        self.session == session_id() && self.user == user_name() && self.host == host_name()
+    }
+
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.time))
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.age() > LOCK_TIMEOUT
+    }
+
+    fn read(image_path: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(lock_filename_for(image_path)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+}
+
+impl Default for LabelLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The lock sidecar next to an image and its label file
+pub fn lock_filename_for(image_path: &Path) -> PathBuf {
+    labels_filename_for(image_path).with_extension("lock")
+}
+
+/// The lock someone else holds on the labels of an image, unless it has expired
+pub fn locked_by_other(image_path: &Path) -> Option<LabelLock> {
+    LabelLock::read(image_path).filter(|lock| !lock.is_mine() && !lock.is_stale())
+}
+
+/// Create the lock file unless it exists. Only one annotator can create it if several open
+/// the image at once. Returns whether the lock was created.
+fn create_lock_file(lock_file: &Path, contents: &str) -> Result<bool> {
+    match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(lock_file)
+    {
+        Ok(mut f) => {
+            f.write_all(contents.as_bytes())?;
+            Ok(true)
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Take or renew the lock on the labels of an image. If someone else holds it, their
+/// lock is returned instead.
+pub fn acquire_lock(image_path: &Path) -> Result<Option<LabelLock>> {
+    let lock_file = lock_filename_for(image_path);
+    let contents = serde_json::to_string_pretty(&LabelLock::new())?;
+    // An expired or unreadable lock is removed and created anew, once
+    for _ in 0..2 {
+        if create_lock_file(&lock_file, &contents)? {
+            break;
+        }
+        match LabelLock::read(image_path) {
+            Some(lock) if lock.is_mine() => {
+                std::fs::write(&lock_file, &contents)?;
+                return Ok(None);
+            }
+            Some(lock) if !lock.is_stale() => return Ok(Some(lock)),
+            _ => match std::fs::remove_file(&lock_file) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+    }
+    // Several annotators may have replaced the same expired lock, the one whose lock
+    // remains holds it
+    match LabelLock::read(image_path) {
+        Some(lock) if lock.is_mine() => Ok(None),
+        Some(lock) => Ok(Some(lock)),
+        None => Err(anyhow!("Could not take the lock {}", lock_file.display())),
+    }
+}
+
+/// Remove the lock on the labels of an image, if it is ours
+pub fn release_lock(image_path: &Path) {
+    if LabelLock::read(image_path)
+        .map(|lock| lock.is_mine())
+        .unwrap_or_default()
+    {
+        _ = std::fs::remove_file(lock_filename_for(image_path));
+    }
+}
+
+/// A lock we hold, renewed in the background so it doesn't expire while the image stays
+/// open. It is released when dropped.
+#[derive(Debug)]
+pub struct HeldLock {
+    image_path: PathBuf,
+    stop: Sender<()>,
+    renewer: Option<JoinHandle<()>>,
+}
+
+impl HeldLock {
+    /// Keep renewing a lock taken with `acquire_lock`
+    pub fn new(image_path: &Path) -> Self {
+        let (stop, stopped) = channel();
+        let path = image_path.to_path_buf();
+        let renewer = std::thread::spawn(move || loop {
+            match stopped.recv_timeout(LOCK_REFRESH_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {
+                    // Someone took over a lock that expired anyway, so it isn't ours to renew
+                    if !LabelLock::read(&path)
+                        .map(|lock| lock.is_mine())
+                        .unwrap_or_default()
+                    {
+                        break;
+                    }
+                    if let Ok(contents) = serde_json::to_string_pretty(&LabelLock::new()) {
+                        _ = std::fs::write(lock_filename_for(&path), contents);
+                    }
+                }
+                _ => break,
+            }
+        });
+        Self {
+            image_path: image_path.to_path_buf(),
+            stop,
+            renewer: Some(renewer),
+        }
+    }
+
+    pub fn image_path(&self) -> &Path {
+        &self.image_path
+    }
+
+    /// Whether the lock is still renewed. It isn't once someone else took it over.
+    pub fn is_held(&self) -> bool {
+        self.renewer
+            .as_ref()
+            .map(|renewer| !renewer.is_finished())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        _ = self.stop.send(());
+        if let Some(renewer) = self.renewer.take() {
+            _ = renewer.join();
+        }
+        release_lock(&self.image_path);
+    }
+}
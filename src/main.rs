@@ -16,10 +16,21 @@ use notan::prelude::*;
 use shortcuts::key_pressed;
 use std::path::PathBuf;
 use std::sync::mpsc;
+pub mod batch;
 pub mod cache;
+pub mod dataset;
+pub mod folder_index;
+pub mod image_filter;
+pub mod job;
+pub mod lint;
+pub mod lock;
+pub mod progress;
+pub mod project;
+pub mod query;
 pub mod scrubber;
 pub mod settings;
 pub mod shortcuts;
+pub mod thumbnails;
 #[cfg(feature = "turbo")]
 use crate::scrubber::find_first_image_in_directory;
 use crate::settings::set_system_theme;
@@ -41,8 +52,8 @@ mod ui;
 #[cfg(feature = "update")]
 mod update;
 use ui::*;
+mod tracks;
 mod yolo_labels;
-use yolo_labels::Unnormaliser;
 
 pub const FONT: &[u8; 309828] = include_bytes!("../res/fonts/Inter-Regular.ttf");
 
@@ -158,6 +169,13 @@ fn init(gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteState {
                 .help("Listen on port")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("recursive")
+                .short('r')
+                .long("recursive")
+                .takes_value(false)
+                .help("Include the images of all subfolders"),
+        )
         .arg(
             Arg::new("chainload")
                 .required(false)
@@ -202,7 +220,24 @@ fn init(gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteState {
         let mut start_img_location: Option<PathBuf> = None;
 
         if let Ok(maybe_location_metadata) = location.metadata() {
-            if maybe_location_metadata.is_dir() {
+            let source = if maybe_location_metadata.is_dir() && matches.is_present("recursive") {
+                Some(dataset::DatasetSource::Recursive(location.clone()))
+            } else if dataset::is_dataset_list(location) {
+                Some(dataset::DatasetSource::list(location))
+            } else {
+                None
+            };
+
+            if let Some(source) = source {
+                // Folder tree or list file - Pick the first image of the dataset
+                match dataset::Dataset::open(source) {
+                    Ok(dataset) => {
+                        start_img_location = dataset.entries.first().cloned();
+                        state.dataset = Some(dataset);
+                    }
+                    Err(e) => state.send_message_err(&format!("Can't open dataset: {e}")),
+                }
+            } else if maybe_location_metadata.is_dir() {
                 // Folder - Pick first image from the folder...
                 if let Ok(first_img_location) = find_first_image_in_directory(location) {
                     start_img_location = Some(first_img_location);
@@ -318,6 +353,8 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
         }
         Event::KeyDown { .. } => {
             debug!("key down");
+            // The labels someone else is editing are read-only
+            let editable = state.locked_by.is_none();
 
             // return;
             // pan image with keyboard
@@ -385,6 +422,25 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
                     next_image(state)
                 }
             }
+            if key_pressed(app, state, NextUnannotated) {
+                jump_to_image(state, image_filter::ImageFilter::Unannotated, true);
+            }
+            if key_pressed(app, state, NextEmptyLabels) {
+                jump_to_image(state, image_filter::ImageFilter::EmptyLabels, true);
+            }
+            if key_pressed(app, state, NextWithClass) {
+                let filter = image_filter::ImageFilter::WithClass(state.current_label_class);
+                jump_to_image(state, filter, true);
+            }
+            if key_pressed(app, state, NextFlagged) {
+                jump_to_image(state, image_filter::ImageFilter::Flagged, true);
+            }
+            if key_pressed(app, state, NextFiltered) {
+                jump_to_image(state, state.image_filter, true);
+            }
+            if key_pressed(app, state, PreviousFiltered) {
+                jump_to_image(state, state.image_filter, false);
+            }
             if key_pressed(app, state, PreviousImage) {
                 if state.is_loaded {
                     prev_image(state)
@@ -412,13 +468,89 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
                 state.show_crosshair_lines = !state.show_crosshair_lines;
             }
             #[cfg(not(target_os = "netbsd"))]
-            if key_pressed(app, state, DeleteAnnoation) {
-                if let Some(id) = state.selected_bbox_id {
-                    state.annotation_bboxes.remove(id);
-                    state.selected_bbox_id = None;
-                    state.send_message("Deleted annotation");
+            if editable && key_pressed(app, state, DeleteAnnoation) {
+                delete_selected_annotation(state);
+            }
+            if key_pressed(app, state, SelectNextAnnotation) {
+                cycle_selected_annotation(state, true);
+            }
+            if key_pressed(app, state, SelectPreviousAnnotation) {
+                cycle_selected_annotation(state, false);
+            }
+            if key_pressed(app, state, DeselectAnnotation) {
+                state.selected_bbox_id = None;
+            }
+            // nudge the selected annotation
+            for (command, delta) in [
+                (MoveAnnotationLeft, Vector2::new(-1., 0.)),
+                (MoveAnnotationRight, Vector2::new(1., 0.)),
+                (MoveAnnotationUp, Vector2::new(0., -1.)),
+                (MoveAnnotationDown, Vector2::new(0., 1.)),
+                (MoveAnnotationLeftFast, Vector2::new(-10., 0.)),
+                (MoveAnnotationRightFast, Vector2::new(10., 0.)),
+                (MoveAnnotationUpFast, Vector2::new(0., -10.)),
+                (MoveAnnotationDownFast, Vector2::new(0., 10.)),
+            ] {
+                if editable && key_pressed(app, state, command) {
+                    move_selected_annotation(state, delta);
+                }
+            }
+            // growing moves the edge in arrow direction out, shrinking moves the opposite edge in
+            for (command, part, delta) in [
+                (GrowAnnotationLeft, BoundingBoxPart::EdgeLeft, -1.),
+                (GrowAnnotationRight, BoundingBoxPart::EdgeRight, 1.),
+                (GrowAnnotationUp, BoundingBoxPart::EdgeTop, -1.),
+                (GrowAnnotationDown, BoundingBoxPart::EdgeBottom, 1.),
+                (ShrinkAnnotationLeft, BoundingBoxPart::EdgeRight, -1.),
+                (ShrinkAnnotationRight, BoundingBoxPart::EdgeLeft, 1.),
+                (ShrinkAnnotationUp, BoundingBoxPart::EdgeBottom, -1.),
+                (ShrinkAnnotationDown, BoundingBoxPart::EdgeTop, 1.),
+            ] {
+                if editable && key_pressed(app, state, command) {
+                    move_selected_annotation_edge(state, part, delta);
                 }
             }
+            if editable && key_pressed(app, state, SetKeyframe) {
+                tracks::set_keyframe(state);
+            }
+            if editable && key_pressed(app, state, InterpolateKeyframe) {
+                tracks::interpolate_from_keyframe(state);
+            }
+            if editable && key_pressed(app, state, AssignTrackIds) {
+                tracks::assign_track_ids(state);
+            }
+            if key_pressed(app, state, SelectTool) {
+                state.tool = Tool::Select;
+            }
+            if editable && key_pressed(app, state, DrawTool) {
+                state.tool = Tool::Draw;
+            }
+            if editable && key_pressed(app, state, StampMode) {
+                state.tool = Tool::Stamp;
+            }
+            if key_pressed(app, state, PanTool) {
+                state.tool = Tool::Pan;
+            }
+            if key_pressed(app, state, HideAnnotations) {
+                state.annotation_visibility.hide_all = !state.annotation_visibility.hide_all;
+            }
+            if key_pressed(app, state, ZoomToAnnotation) {
+                zoom_to_selected_annotation(state);
+            }
+            if key_pressed(app, state, ReviewNext) {
+                review_step(state, true);
+            }
+            if key_pressed(app, state, ReviewPrevious) {
+                review_step(state, false);
+            }
+            if key_pressed(app, state, ThumbnailGrid) {
+                state.persistent_settings.show_thumbnails =
+                    !state.persistent_settings.show_thumbnails;
+            }
+            if key_pressed(app, state, AnnotationTags) {
+                state.persistent_settings.show_annotation_tags =
+                    !state.persistent_settings.show_annotation_tags;
+            }
             if key_pressed(app, state, ZoomIn) {
                 let delta = zoomratio(3.5, state.image_geometry.scale);
                 let new_scale = state.image_geometry.scale + delta;
@@ -500,7 +632,11 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
             );
             state.persistent_settings.save_blocking();
 
-            write_annotions_to_file(state);
+            // There is no time to ask about labels changed on disk, so both sides are kept
+            if !write_annotions_to_file(state) {
+                merge_label_conflict(state);
+            }
+            release_current_lock(state);
         }
         Event::MouseWheel { delta_y, .. } => {
             if !state.pointer_over_ui {
@@ -512,6 +648,10 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
                     } else {
                         next_image(state)
                     }
+                } else if state.tool == Tool::Stamp && app.keyboard.shift() {
+                    // Scale the stamp
+                    let factor = if delta_y > 0.0 { 1.1 } else { 1.0 / 1.1 };
+                    state.stamp_scale = (state.stamp_scale * factor).max(0.05).min(20.);
                 } else {
                     let divisor = if cfg!(macos) { 0.1 } else { 10. };
                     // Normal scaling
@@ -537,7 +677,9 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
 
         Event::Drop(file) => {
             if let Some(p) = file.path {
-                if let Some(ext) = p.extension() {
+                if dataset::is_dataset_list(&p) {
+                    open_dataset(state, dataset::DatasetSource::list(&p));
+                } else if let Some(ext) = p.extension() {
                     if SUPPORTED_EXTENSIONS.contains(&ext.to_string_lossy().to_string().as_str()) {
                         state.is_loaded = false;
                         state.current_image = None;
@@ -554,25 +696,82 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
                 if !state.mouse_grab {
                     state.drag_enabled = true;
                 }
+                if button == MouseButton::Right {
+                    state.right_click_start = Some(state.cursor);
+                }
             }
             MouseButton::Left => {
-                if state.cursor_within_image {
+                if state.annotation_context_menu.is_some() && state.pointer_over_ui {
+                    // The click belongs to the context menu
+                } else if state.tool == Tool::Pan {
+                    if !state.mouse_grab {
+                        state.drag_enabled = true;
+                    }
+                } else if state.cursor_within_image && state.locked_by.is_some() {
+                    // Boxes can only be selected in labels someone else is editing
+                    state.selected_bbox_id = annotation_at(state, state.cursor_relative);
+                } else if state.cursor_within_image && state.tool == Tool::Stamp {
+                    stamp_annotation(state);
+                } else if state.cursor_within_image {
+                    let new_box_class = match state.tool {
+                        Tool::Draw => Some(state.current_label_class),
+                        _ => None,
+                    };
                     state.bbox_edit_mode.mouse_button_down(
                         state.cursor_relative,
                         &mut state.annotation_bboxes,
                         &mut state.selected_bbox_id,
-                        state.current_label_class,
+                        new_box_class,
+                        state.persistent_settings.two_click_drawing,
+                        &state.annotation_visibility,
                     );
                 }
             }
             _ => {}
         },
         Event::MouseUp { button, .. } => match button {
-            MouseButton::Right | MouseButton::Middle => state.drag_enabled = false,
+            MouseButton::Right => {
+                state.drag_enabled = false;
+                // A right click without dragging opens the menu of the box under the cursor
+                let moved = state
+                    .right_click_start
+                    .take()
+                    .map(|start| (state.cursor - start).norm())
+                    .unwrap_or(f32::INFINITY);
+                if moved < 4.0 && state.cursor_within_image {
+                    if let Some(bbox_id) = annotation_at(state, state.cursor_relative) {
+                        state.selected_bbox_id = Some(bbox_id);
+                        state.annotation_context_menu = Some(AnnotationContextMenu {
+                            bbox_id,
+                            position: state.cursor,
+                        });
+                    }
+                }
+            }
+            MouseButton::Middle => state.drag_enabled = false,
             MouseButton::Left => {
-                state
-                    .bbox_edit_mode
-                    .mouse_button_up(state.cursor_relative, &mut state.annotation_bboxes);
+                if state.tool == Tool::Pan {
+                    state.drag_enabled = false;
+                }
+                let edited_bbox_id = state.bbox_edit_mode.bbox_id();
+                let min_size = state.persistent_settings.min_box_size.max(
+                    state.persistent_settings.min_box_screen_size / state.image_geometry.scale,
+                );
+                state.bbox_edit_mode.mouse_button_up(
+                    state.cursor_relative,
+                    &mut state.annotation_bboxes,
+                    min_size,
+                    state.image_dimension,
+                );
+
+                // Remember the size for the stamp tool
+                if let Some(bbox) = edited_bbox_id.and_then(|id| state.annotation_bboxes.get(id)) {
+                    if bbox.width() > 0.0 && bbox.height() > 0.0 {
+                        state
+                            .remembered_box_sizes
+                            .insert(bbox.class_id, bbox.size());
+                    }
+                }
             }
             _ => {}
         },
@@ -615,9 +814,31 @@ fn update(app: &mut App, state: &mut OculanteState) {
         state.image_geometry.scale,
     );
 
-    state
-        .bbox_edit_mode
-        .update(state.cursor_relative, &mut state.annotation_bboxes);
+    // Alt draws from the centre, shift keeps the aspect ratio
+    let draw_modifiers = DrawModifiers {
+        from_center: app.keyboard.alt(),
+        aspect_ratio: if app.keyboard.shift() {
+            Some(state.persistent_settings.draw_aspect_ratio)
+        } else {
+            None
+        },
+    };
+    // Ctrl temporarily disables all snapping
+    let snapping = !app.keyboard.ctrl();
+    let edited_id = state.bbox_edit_mode.bbox_id();
+    let snap_targets = match edited_id {
+        Some(id) if snapping => snap_targets(state, Some(id)),
+        _ => SnapTargets::default(),
+    };
+    state.snap_guides = state.bbox_edit_mode.update(
+        state.cursor_relative,
+        &mut state.annotation_bboxes,
+        draw_modifiers,
+        &snap_targets,
+    );
+    if let Some(id) = edited_id.filter(|_| snapping) {
+        snap_annotation(state, id);
+    }
 
     if state.drag_enabled {
         if !state.mouse_grab || app.mouse.is_down(MouseButton::Middle) {
@@ -669,13 +890,17 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
     let mut draw = gfx.create_draw();
 
     if let Ok(p) = state.load_channel.1.try_recv() {
-        state.is_loaded = false;
-        state.current_image = None;
-        state.player.load(&p, state.message_channel.0.clone());
         if let Some(dir) = p.parent() {
             state.persistent_settings.last_open_directory = dir.to_path_buf();
         }
-        state.current_path = Some(p);
+        if dataset::is_dataset_list(&p) {
+            open_dataset(state, dataset::DatasetSource::list(&p));
+        } else {
+            state.is_loaded = false;
+            state.current_image = None;
+            state.player.load(&p, state.message_channel.0.clone());
+            state.current_path = Some(p);
+        }
         _ = state.persistent_settings.save();
     }
 
@@ -690,10 +915,12 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
 
         set_title(app, state);
 
+        // A new folder is indexed before the image sequence is filled from it
+        update_project(state);
+
         // fill image sequence
         if let Some(p) = &state.current_path {
-            state.scrubber = scrubber::Scrubber::new(p);
-            state.scrubber.wrap = state.persistent_settings.wrap_folder;
+            state.scrubber = scrubber_for(state, p);
 
             // debug!("{:#?} from {}", &state.scrubber, p.display());
             if !state.persistent_settings.recent_images.contains(p) {
@@ -701,6 +928,10 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 state.persistent_settings.recent_images.truncate(10);
             }
         }
+        if let Some(query) = &state.dataset_query {
+            state.scrubber.restrict(&query.matches);
+        }
+        mark_visited(state);
 
         match frame.source {
             FrameSource::Still => {
@@ -759,26 +990,34 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             );
         }
 
-        // Remove all previous annotations
-        state.annotation_bboxes.clear();
-
-        // Load annotations from file, if they are available
-        let labels_filename = get_labels_filename(state);
-        if let Ok(labels) = yolo_labels::Labels::from_file(labels_filename) {
-            for label in labels.labels {
-                let label_img =
-                    label.unnormalise((state.image_dimension.0, state.image_dimension.1));
-                state
-                    .annotation_bboxes
-                    .push(AnnoationBoundingBox::from_center(
-                        label_img.x_centre,
-                        label_img.y_centre,
-                        label_img.width,
-                        label_img.height,
-                        label_img.label_index as u32,
-                    ));
-            }
-        }
+        // Replace all previous annotations by the ones from file, if they are available
+        state.selected_bbox_id = None;
+        load_annotations_from_file(state);
+        lock_current_image(state);
+    }
+
+    set_title(app, state);
+    // The index watches the folders in the background. The loop only runs on events and
+    // can't be woken from another thread, so what it found while the window was idle is
+    // shown with the next event, like the pointer moving.
+    if update_folder_index(state) {
+        app.window().request_frame();
+    }
+    check_label_file(state);
+    refresh_lock(state);
+    update_query(state);
+    update_image_search(state);
+    tracks::update_track_operation(state);
+    if state.query_job.is_some()
+        || state.image_search_job.is_some()
+        || state.track_operation_job.is_some()
+        || !state.folder_index.as_ref().map(|i| i.ready).unwrap_or(true)
+    {
+        app.window().request_frame();
+    }
+    if !state.progress.counts.is_complete() {
+        state.progress.update(&state.project, state.folder_index.as_ref());
+        app.window().request_frame();
     }
 
     if state.redraw {
@@ -803,6 +1042,13 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         // app.window().request_frame();
     }
 
+    // Continue a review walk once the next image is shown
+    if state.is_loaded {
+        if let Some(walk) = state.review_walk.take() {
+            continue_review_walk(state, walk);
+        }
+    }
+
     // TODO: Do we need/want a "global" checker?
     // if state.persistent_settings.show_checker_background {
     //     if let Some(checker) = &state.checker_texture {
@@ -873,6 +1119,9 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
 
         {
             for (current_id, bbox) in state.annotation_bboxes.iter().enumerate() {
+                if !state.annotation_visibility.is_visible(bbox) {
+                    continue;
+                }
                 let mut fill = false;
                 state.selected_bbox_id.and_then(|selected_bbox_id| {
                     if selected_bbox_id == current_id {
@@ -881,11 +1130,15 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                     Some(selected_bbox_id)
                 });
 
-                let line_color = state.label_colors[bbox.class_id as usize];
+                let mut line_color = annotation_color(state, bbox);
+                // Interpolated boxes are drawn faded until they have been reviewed
+                if bbox.interpolated {
+                    line_color.a *= 0.5;
+                }
                 let mut fill_color = line_color;
 
                 if fill {
-                    fill_color.a = 0.25;
+                    fill_color.a = 0.25 * line_color.a;
                 } else {
                     fill_color.a = 0.0;
                 }
@@ -909,9 +1162,53 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             }
         }
 
+        // Guides for the lines the edited box snapped to
+        {
+            let color = Color::new(0.0, 1.0, 1.0, 0.8);
+            let line_width = 1.0 / state.image_geometry.scale;
+            if let Some(x) = state.snap_guides.x {
+                draw.line((x, 0.0), (x, texture.height()))
+                    .width(line_width)
+                    .color(color)
+                    .scale(state.image_geometry.scale, state.image_geometry.scale)
+                    .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
+            }
+            if let Some(y) = state.snap_guides.y {
+                draw.line((0.0, y), (texture.width(), y))
+                    .width(line_width)
+                    .color(color)
+                    .scale(state.image_geometry.scale, state.image_geometry.scale)
+                    .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
+            }
+        }
+
+        // Preview of the box a click would place
+        if state.tool == Tool::Stamp && state.cursor_within_image && !state.pointer_over_ui {
+            let (width, height) = stamp_size(state, state.current_label_class);
+            let line_width = f32::max(0.5, 1.5 / state.image_geometry.scale);
+            draw.rect(
+                (
+                    state.cursor_relative.x - width / 2.0,
+                    state.cursor_relative.y - height / 2.0,
+                ),
+                (width, height),
+            )
+            .stroke(line_width)
+            .color(
+                state.label_colors[state.current_label_class as usize % state.label_colors.len()],
+            )
+            .blend_mode(BlendMode::NORMAL)
+            .scale(state.image_geometry.scale, state.image_geometry.scale)
+            .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
+        }
+
         state.current_bounding_box_element_under_cursor = state
             .bbox_edit_mode
-            .get_part_element(state.cursor_relative, &state.annotation_bboxes);
+            .get_part_element(
+                state.cursor_relative,
+                &state.annotation_bboxes,
+                &state.annotation_visibility,
+            );
 
         // if let Some(bbox_element) = state.current_bounding_box_element_under_cursor {
         //     let bbox = &state.annotation_bboxes[bbox_element.id];
@@ -1019,6 +1316,10 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         //     }
         // }
 
+        if state.persistent_settings.show_loupe {
+            draw_loupe(&mut draw, texture, state);
+        }
+
         if state.persistent_settings.show_minimap {
             // let offset_x = app.window().size().0 as f32 - state.image_dimension.0 as f32;
             let offset_x = 0.0;
@@ -1042,8 +1343,19 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         // Default cursor
         ctx.set_cursor_icon(CursorIcon::Default);
 
+        // Cursor of the tool
+        if state.cursor_within_image && !state.pointer_over_ui {
+            match state.tool {
+                Tool::Select => {}
+                Tool::Draw => ctx.set_cursor_icon(CursorIcon::Crosshair),
+                Tool::Stamp => ctx.set_cursor_icon(CursorIcon::Cell),
+                Tool::Pan if state.drag_enabled => ctx.set_cursor_icon(CursorIcon::Grabbing),
+                Tool::Pan => ctx.set_cursor_icon(CursorIcon::Grab),
+            }
+        }
+
         // Special cursors
-        if state.cursor_within_image {
+        if state.cursor_within_image && matches!(state.tool, Tool::Select | Tool::Draw) {
             if let Some(element_under_cursor) = state.current_bounding_box_element_under_cursor {
                 match element_under_cursor.part {
                     BoundingBoxPart::EdgeBottom | BoundingBoxPart::EdgeTop => {
@@ -1088,6 +1400,13 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             }
         }
 
+        if state.persistent_settings.show_annotation_tags {
+            annotation_tags(ctx, state);
+        }
+
+        annotation_context_menu(ctx, state);
+        annotation_readout(ctx, state);
+
         if !state.persistent_settings.zen_mode {
             egui::TopBottomPanel::top("menu")
                 .min_height(30.)
@@ -1097,6 +1416,21 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 });
         }
 
+        if let Some(lock) = state.locked_by.clone() {
+            egui::TopBottomPanel::top("lock banner").show(ctx, |ui| {
+                lock_banner_ui(ui, &lock);
+            });
+        }
+
+        if state.persistent_settings.show_status_bar
+            && !state.persistent_settings.zen_mode
+            && state.current_path.is_some()
+        {
+            egui::TopBottomPanel::bottom("status bar").show(ctx, |ui| {
+                status_bar_ui(ui, state);
+            });
+        }
+
         if state.persistent_settings.show_scrub_bar {
             egui::TopBottomPanel::bottom("scrubber")
                 .max_height(22.)
@@ -1149,6 +1483,17 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             }
         }
 
+        if state.persistent_settings.show_thumbnails
+            && !state.persistent_settings.zen_mode
+            && state.current_path.is_some()
+        {
+            thumbnail_grid(ctx, state);
+        }
+
+        if state.label_conflict.is_some() {
+            label_conflict_ui(ctx, state);
+        }
+
         if state.persistent_settings.info_enabled
             && !state.settings_enabled
             && !state.persistent_settings.zen_mode
@@ -1218,6 +1563,7 @@ fn browse_for_image_path(state: &mut OculanteState) {
     std::thread::spawn(move || {
         let file_dialog_result = rfd::FileDialog::new()
             .add_filter("All Supported Image Types", utils::SUPPORTED_EXTENSIONS)
+            .add_filter("Dataset lists", &["txt", "yaml", "yml"])
             .add_filter("All File Types", &["*"])
             .set_directory(start_directory)
             .pick_file();
@@ -1248,6 +1594,98 @@ fn limit_offset(app: &mut App, state: &mut OculanteState) {
         .max(-scaled_image_size.1);
 }
 
+/// Draw a magnified view of the image next to the cursor while a box corner or edge is placed
+fn draw_loupe(draw: &mut Draw, texture: &Texture, state: &OculanteState) {
+    let bbox = match state.bbox_edit_mode {
+        BoundingBoxEditMode::New { id, .. }
+        | BoundingBoxEditMode::DragCorner { id, .. }
+        | BoundingBoxEditMode::DragEdge { id, .. } => match state.annotation_bboxes.get(id) {
+            Some(bbox) => *bbox,
+            None => return,
+        },
+        _ => return,
+    };
+
+    let loupe_size = 160.0;
+    let zoom = state.persistent_settings.loupe_zoom;
+    let half_extent = Vector2::new(loupe_size, loupe_size) / zoom / 2.0;
+    // The part of the image shown in the loupe
+    let src_min = state.cursor_relative - half_extent;
+    let src_max = state.cursor_relative + half_extent;
+
+    // Keep the loupe next to the cursor, but within the window
+    let gap = 24.0;
+    let mut pos = state.cursor + Vector2::new(gap, gap);
+    if pos.x + loupe_size > state.window_size.x {
+        pos.x = state.cursor.x - gap - loupe_size;
+    }
+    if pos.y + loupe_size > state.window_size.y {
+        pos.y = state.cursor.y - gap - loupe_size;
+    }
+    let to_loupe = |p: Vector2<f32>| pos + (p - src_min) * zoom;
+
+    draw.rect((pos.x, pos.y), (loupe_size, loupe_size))
+        .color(Color::BLACK);
+
+    let crop_min = Vector2::new(src_min.x.max(0.0), src_min.y.max(0.0));
+    let crop_max = Vector2::new(
+        src_max.x.min(texture.width()),
+        src_max.y.min(texture.height()),
+    );
+    if crop_max.x > crop_min.x && crop_max.y > crop_min.y {
+        let dest = to_loupe(crop_min);
+        let crop_size = crop_max - crop_min;
+        // The texture is magnified with nearest filtering, so pixels stay sharp
+        draw.image(texture)
+            .blend_mode(BlendMode::NORMAL)
+            .crop((crop_min.x, crop_min.y), (crop_size.x, crop_size.y))
+            .position(dest.x, dest.y)
+            .size(crop_size.x * zoom, crop_size.y * zoom);
+    }
+
+    // Edges of the box, clipped to the loupe
+    let color = Color {
+        a: 1.0,
+        ..annotation_color(state, &bbox)
+    };
+    for (from, to) in [
+        (bbox.tl_corner(), bbox.tr_corner()),
+        (bbox.bl_corner(), bbox.br_corner()),
+        (bbox.tl_corner(), bbox.bl_corner()),
+        (bbox.tr_corner(), bbox.br_corner()),
+    ] {
+        let outside = (from.x == to.x && (from.x < src_min.x || from.x > src_max.x))
+            || (from.y == to.y && (from.y < src_min.y || from.y > src_max.y));
+        if outside {
+            continue;
+        }
+        let clip = |p: Vector2<f32>| {
+            Vector2::new(
+                p.x.clamp(src_min.x, src_max.x),
+                p.y.clamp(src_min.y, src_max.y),
+            )
+        };
+        let (from, to) = (to_loupe(clip(from)), to_loupe(clip(to)));
+        draw.line((from.x, from.y), (to.x, to.y))
+            .width(2.0)
+            .color(color);
+    }
+
+    // Crosshair
+    let center = pos + Vector2::new(loupe_size, loupe_size) / 2.0;
+    let crosshair_color = Color::new(1.0, 1.0, 1.0, 0.6);
+    draw.line((center.x, pos.y), (center.x, pos.y + loupe_size))
+        .width(1.0)
+        .color(crosshair_color);
+    draw.line((pos.x, center.y), (pos.x + loupe_size, center.y))
+        .width(1.0)
+        .color(crosshair_color);
+
+    draw.rect((pos.x, pos.y), (loupe_size, loupe_size))
+        .stroke(2.0)
+        .color(Color::WHITE);
+}
+
 fn set_zoom(scale: f32, from_center: Option<Vector2<f32>>, state: &mut OculanteState) {
     let delta = scale - state.image_geometry.scale;
     let zoom_point = from_center.unwrap_or(state.cursor);
@@ -0,0 +1,140 @@
+use crate::folder_index::FolderIndex;
+use crate::project::{ImageStatus, ProjectSettings};
+use crate::tracks::read_labels_for_image;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Label files read per frame while counting without an index, so large folders don't
+/// stall the UI
+const IMAGES_PER_UPDATE: usize = 200;
+
+/// What is known about one image of the folder
+#[derive(Debug, Clone, Copy)]
+struct ImageProgress {
+    boxes: usize,
+    status: ImageStatus,
+}
+
+impl ImageProgress {
+    fn is_annotated(self) -> bool {
+        self.boxes > 0 || self.status == ImageStatus::Done
+    }
+
+    fn is_background(self) -> bool {
+        !self.is_annotated() && self.status == ImageStatus::Background
+    }
+}
+
+/// Totals for the folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProgressCounts {
+    pub images: usize,
+    /// Images whose labels have been read so far
+    pub counted: usize,
+    /// Images with boxes or marked as done
+    pub annotated: usize,
+    /// Images marked as background which have no boxes
+    pub background: usize,
+}
+
+impl ProgressCounts {
+    /// Share of images which are annotated or background
+    pub fn fraction(&self) -> f32 {
+        if self.images == 0 {
+            return 0.;
+        }
+        (self.annotated + self.background) as f32 / self.images as f32
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.counted == self.images
+    }
+}
+
+/// Counts the annotation progress of a folder. Boxes are taken from the folder index, or
+/// else read a few label files at a time, and then kept up to date as images are saved.
+#[derive(Debug, Default)]
+pub struct ProgressCounter {
+    folder: Option<PathBuf>,
+    entries: Vec<PathBuf>,
+    images: HashMap<PathBuf, ImageProgress>,
+    pub counts: ProgressCounts,
+}
+
+impl ProgressCounter {
+    /// Count the images of `entries`, whose status is stored in the project of `folder`
+    pub fn new(folder: Option<&Path>, entries: Vec<PathBuf>) -> Self {
+        Self {
+            folder: folder.map(|f| f.to_path_buf()),
+            counts: ProgressCounts {
+                images: entries.len(),
+                ..Default::default()
+            },
+            entries,
+            images: Default::default(),
+        }
+    }
+
+    /// Count the images the index has summaries for, and read a few label files it has not
+    pub fn update(&mut self, project: &ProjectSettings, index: Option<&FolderIndex>) {
+        let folder = match &self.folder {
+            Some(folder) => folder.clone(),
+            None => return,
+        };
+        let mut reads = 0;
+        while self.counts.counted < self.entries.len() && reads < IMAGES_PER_UPDATE {
+            let image_path = self.entries[self.counts.counted].clone();
+            let boxes = match index.and_then(|i| i.labels.get(&image_path)) {
+                Some(summary) => summary.boxes,
+                // The index reads it soon
+                None if index.map(|i| !i.ready).unwrap_or_default() => return,
+                None => {
+                    reads += 1;
+                    read_labels_for_image(&image_path).len()
+                }
+            };
+            let progress = ImageProgress {
+                boxes,
+                status: project.image_status(&folder, &image_path),
+            };
+            self.tally(progress, true);
+            self.images.insert(image_path, progress);
+            self.counts.counted += 1;
+        }
+    }
+
+    fn tally(&mut self, progress: ImageProgress, add: bool) {
+        let change = |count: &mut usize, applies: bool| {
+            if applies {
+                if add {
+                    *count += 1;
+                } else {
+                    *count = count.saturating_sub(1);
+                }
+            }
+        };
+        change(&mut self.counts.annotated, progress.is_annotated());
+        change(&mut self.counts.background, progress.is_background());
+    }
+
+    fn change_image(&mut self, image_path: &Path, edit: impl FnOnce(&mut ImageProgress)) {
+        // Images not counted yet are picked up with their new state when they are reached
+        if let Some(mut progress) = self.images.get(image_path).cloned() {
+            self.tally(progress, false);
+            edit(&mut progress);
+            self.tally(progress, true);
+            self.images.insert(image_path.to_path_buf(), progress);
+        }
+    }
+
+    /// An image was saved with this many boxes
+    pub fn set_boxes(&mut self, image_path: &Path, boxes: usize) {
+        self.change_image(image_path, |p| p.boxes = boxes);
+    }
+
+    pub fn set_status(&mut self, image_path: &Path, status: ImageStatus) {
+        self.change_image(image_path, |p| p.status = status);
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// Name of the project file inside the image folder
+pub const PROJECT_FILE: &str = "oculante_project.json";
+
+/// Class names as written by most YOLO tools, one per line
+pub const CLASS_NAMES_FILE: &str = "classes.txt";
+
+/// How far an image has been annotated
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImageStatus {
+    /// Never opened
+    #[default]
+    Unvisited,
+    /// Opened, but not finished
+    Visited,
+    /// All objects are annotated
+    Done,
+    /// Contains no objects on purpose
+    Background,
+}
+
+impl ImageStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageStatus::Unvisited => "Unvisited",
+            ImageStatus::Visited => "Visited",
+            ImageStatus::Done => "Done",
+            ImageStatus::Background => "Background",
+        }
+    }
+}
+
+/// The order the images of the folder are moved through in
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Natural order of the names, or the order of the list file for datasets
+    #[default]
+    Name,
+    /// Oldest image first
+    Modified,
+    /// Smallest image first
+    Size,
+    /// Fewest boxes first
+    Boxes,
+    /// Images without labels first, then the least recently edited labels
+    LabelsEdited,
+    /// A random order which is the same for the same seed
+    Shuffle(u64),
+}
+
+impl SortOrder {
+    pub fn name(self) -> &'static str {
+        match self {
+            SortOrder::Name => "Name",
+            SortOrder::Modified => "Date modified",
+            SortOrder::Size => "File size",
+            SortOrder::Boxes => "Box count",
+            SortOrder::LabelsEdited => "Labels edited",
+            SortOrder::Shuffle(_) => "Shuffle",
+        }
+    }
+}
+
+/// Settings belonging to a dataset. They are stored in the image folder, so everyone
+/// annotating the folder shares them.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ProjectSettings {
+    /// Size (width, height) in pixels of stamped boxes, per class
+    pub stamp_sizes: BTreeMap<u32, (f32, f32)>,
+    /// Names of the classes, by class id
+    pub class_names: Vec<String>,
+    /// Status of the images, by path relative to the project folder
+    pub image_status: BTreeMap<String, ImageStatus>,
+    pub sort_order: SortOrder,
+}
+
+impl ProjectSettings {
+    pub fn load(folder: &Path) -> Result<Self> {
+        let f = File::open(folder.join(PROJECT_FILE))?;
+        Ok(serde_json::from_reader::<_, ProjectSettings>(f)?)
+    }
+
+    pub fn save(&self, folder: &Path) -> Result<()> {
+        let f = File::create(folder.join(PROJECT_FILE))?;
+        Ok(serde_json::to_writer_pretty(f, self)?)
+    }
+
+    /// The name of a class, or its id if it has none
+    pub fn class_name(&self, class_id: u32) -> String {
+        self.class_names
+            .get(class_id as usize)
+            .filter(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| format!("{class_id}"))
+    }
+
+    pub fn image_status(&self, folder: &Path, image_path: &Path) -> ImageStatus {
+        self.image_status
+            .get(&image_key(folder, image_path))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set_image_status(&mut self, folder: &Path, image_path: &Path, status: ImageStatus) {
+        let key = image_key(folder, image_path);
+        if status == ImageStatus::Unvisited {
+            self.image_status.remove(&key);
+        } else {
+            self.image_status.insert(key, status);
+        }
+    }
+
+    /// Set the status of images and save it. The statuses in the project file are read
+    /// first, so the ones saved by other annotators in the meantime are kept.
+    pub fn save_image_status(
+        &mut self,
+        folder: &Path,
+        image_paths: &[PathBuf],
+        status: ImageStatus,
+    ) -> Result<()> {
+        if let Ok(on_disk) = Self::load(folder) {
+            self.image_status = on_disk.image_status;
+        }
+        for image_path in image_paths {
+            self.set_image_status(folder, image_path, status);
+        }
+        self.save(folder)
+    }
+}
+
+/// How an image is referred to in the project file, so the folder can be moved
+fn image_key(folder: &Path, image_path: &Path) -> String {
+    image_path
+        .strip_prefix(folder)
+        .unwrap_or(image_path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Read the class names file of a folder
+pub fn read_class_names(folder: &Path) -> Vec<String> {
+    std::fs::read_to_string(folder.join(CLASS_NAMES_FILE))
+        .map(|s| s.lines().map(|l| l.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// The folder a project belongs to, for an image path or folder
+pub fn project_folder(path: &Path) -> Option<PathBuf> {
+    if path.is_dir() {
+        Some(path.to_path_buf())
+    } else {
+        path.parent().map(|p| p.to_path_buf())
+    }
+}
@@ -0,0 +1,220 @@
+use crate::tracks::read_labels_for_image;
+use crate::utils::labels_filename_for;
+use crate::yolo_labels::Label;
+use anyhow::{bail, Result};
+use evalexpr::{eval_boolean_with_context, ContextWithMutableVariables, HashMapContext, Value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Functions which evaluate their argument for every box of an image
+const AGGREGATES: [&str; 3] = ["count", "any", "all"];
+
+/// Variables which need the size of the image, so it is only read if they are used
+const PIXEL_VARIABLES: [&str; 7] = [
+    "px", "py", "width", "height", "area", "min_area", "max_area",
+];
+
+/// Variables describing a single box
+pub const BOX_HELP: &str = "class, prob (or conf), track, interpolated, x, y, w, h (normalised \
+centre and size), px, py, width, height, area (centre and size in pixels)";
+
+/// Help text listing what can be used in a query
+pub const QUERY_HELP: &str = "Per image: boxes, classes, has_labels, has_unknown_class, \
+min_area, max_area, min_prob.\nPer box, inside count(...), any(...) and all(...): class, prob \
+(or conf), track, interpolated, x, y, w, h (normalised centre and size), px, py, width, height, \
+area (centre and size in pixels).\nExample: count(class == 2) > 3 && min_area < 100";
+
+/// A query restricting the images of the folder
+#[derive(Debug, Clone)]
+pub struct DatasetQuery {
+    pub expression: String,
+    pub matches: Vec<PathBuf>,
+}
+
+/// A query being run over the images of the folder in the background
+#[derive(Debug)]
+pub struct QueryRun {
+    pub expression: String,
+    pub entries: Vec<PathBuf>,
+    pub matches: Result<Vec<PathBuf>>,
+}
+
+fn set(context: &mut HashMapContext, name: &str, value: Value) -> Result<()> {
+    Ok(context.set_value(name.into(), value)?)
+}
+
+/// Replace `count(...)`, `any(...)` and `all(...)` by their result over the boxes
+fn expand_aggregates(expression: &str, boxes: &[HashMapContext]) -> Result<String> {
+    let mut expression = expression.to_string();
+    loop {
+        // Find the first aggregate call which is not part of a longer identifier
+        let call = AGGREGATES
+            .iter()
+            .flat_map(|name| {
+                expression
+                    .match_indices(&format!("{name}("))
+                    .map(move |(start, _)| (start, *name))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|(start, _)| {
+                !expression[..*start]
+                    .chars()
+                    .last()
+                    .map(|c| c.is_alphanumeric() || c == '_')
+                    .unwrap_or(false)
+            })
+            .min_by_key(|(start, _)| *start);
+
+        let (start, name) = match call {
+            Some(call) => call,
+            None => return Ok(expression),
+        };
+
+        let open = start + name.len();
+        let mut depth = 0;
+        let mut close = None;
+        for (i, c) in expression[open..].char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(open + i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let close = match close {
+            Some(close) => close,
+            None => bail!("Missing ')' after {name}"),
+        };
+
+        let inner = &expression[open + 1..close];
+        let mut num_true = 0;
+        for context in boxes {
+            if eval_boolean_with_context(inner, context)? {
+                num_true += 1;
+            }
+        }
+        let result = match name {
+            "count" => format!("{num_true}"),
+            "any" => format!("{}", num_true > 0),
+            _ => format!("{}", num_true == boxes.len()),
+        };
+        expression.replace_range(start..=close, &format!("({result})"));
+    }
+}
+
+/// Whether an expression uses a variable, ignoring longer identifiers containing it
+fn uses_variable(expression: &str, name: &str) -> bool {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    expression.match_indices(name).any(|(start, _)| {
+        let before = expression[..start].chars().last();
+        let after = expression[start + name.len()..].chars().next();
+        !before.map(is_identifier).unwrap_or(false) && !after.map(is_identifier).unwrap_or(false)
+    })
+}
+
+/// Size of the image in pixels, if the expression needs it
+pub fn image_size_for(expression: &str, image_path: &Path) -> Result<(f64, f64)> {
+    if PIXEL_VARIABLES.iter().any(|v| uses_variable(expression, v)) {
+        let (width, height) = image::image_dimensions(image_path)?;
+        Ok((width as f64, height as f64))
+    } else {
+        Ok((0.0, 0.0))
+    }
+}
+
+/// The variables describing one box
+pub fn box_context(label: &Label, image_size: (f64, f64)) -> Result<HashMapContext> {
+    let (image_width, image_height) = image_size;
+    let prob = label.probability.unwrap_or(1.0) as f64;
+    let mut context = HashMapContext::new();
+    set(&mut context, "class", Value::Int(label.label_index as i64))?;
+    set(&mut context, "prob", Value::Float(prob))?;
+    set(&mut context, "conf", Value::Float(prob))?;
+    set(
+        &mut context,
+        "track",
+        Value::Int(label.object_id.map(|id| id as i64).unwrap_or(-1)),
+    )?;
+    set(
+        &mut context,
+        "interpolated",
+        Value::Boolean(label.interpolated),
+    )?;
+    set(&mut context, "x", Value::Float(label.x_centre as f64))?;
+    set(&mut context, "y", Value::Float(label.y_centre as f64))?;
+    set(&mut context, "w", Value::Float(label.width as f64))?;
+    set(&mut context, "h", Value::Float(label.height as f64))?;
+    let width = label.width as f64 * image_width;
+    let height = label.height as f64 * image_height;
+    set(
+        &mut context,
+        "px",
+        Value::Float(label.x_centre as f64 * image_width),
+    )?;
+    set(
+        &mut context,
+        "py",
+        Value::Float(label.y_centre as f64 * image_height),
+    )?;
+    set(&mut context, "width", Value::Float(width))?;
+    set(&mut context, "height", Value::Float(height))?;
+    set(&mut context, "area", Value::Float(width * height))?;
+    Ok(context)
+}
+
+/// Evaluate a query for one image, using its label file
+pub fn evaluate_query(expression: &str, image_path: &Path, num_classes: u32) -> Result<bool> {
+    let labels = read_labels_for_image(image_path);
+    let image_size = image_size_for(expression, image_path)?;
+
+    let mut boxes = vec![];
+    let mut min_area = f64::INFINITY;
+    let mut max_area = 0.0_f64;
+    let mut min_prob = 1.0_f64;
+    for label in labels.iter() {
+        let area = label.width as f64 * image_size.0 * label.height as f64 * image_size.1;
+        min_area = min_area.min(area);
+        max_area = max_area.max(area);
+        min_prob = min_prob.min(label.probability.unwrap_or(1.0) as f64);
+        boxes.push(box_context(label, image_size)?);
+    }
+
+    let classes: HashSet<u32> = labels.iter().map(|l| l.label_index).collect();
+    let mut context = HashMapContext::new();
+    set(&mut context, "boxes", Value::Int(labels.len() as i64))?;
+    set(&mut context, "classes", Value::Int(classes.len() as i64))?;
+    set(
+        &mut context,
+        "has_labels",
+        Value::Boolean(labels_filename_for(image_path).exists()),
+    )?;
+    set(
+        &mut context,
+        "has_unknown_class",
+        Value::Boolean(classes.iter().any(|c| *c >= num_classes)),
+    )?;
+    set(&mut context, "min_area", Value::Float(min_area))?;
+    set(&mut context, "max_area", Value::Float(max_area))?;
+    set(&mut context, "min_prob", Value::Float(min_prob))?;
+
+    let expression = expand_aggregates(expression, &boxes)?;
+    Ok(eval_boolean_with_context(&expression, &context)?)
+}
+
+/// The images of `entries` matching a query
+pub fn run_query(expression: &str, entries: &[PathBuf], num_classes: u32) -> Result<Vec<PathBuf>> {
+    let mut matches = vec![];
+    for image_path in entries {
+        match evaluate_query(expression, image_path, num_classes) {
+            Ok(true) => matches.push(image_path.clone()),
+            Ok(false) => {}
+            Err(e) => bail!("{e} ({})", image_path.display()),
+        }
+    }
+    Ok(matches)
+}
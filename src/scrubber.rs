@@ -1,7 +1,10 @@
 use crate::utils::is_ext_compatible;
 use anyhow::{bail, Context, Result};
 use log::debug;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
 #[derive(Debug, Default)]
 pub struct Scrubber {
@@ -20,6 +23,16 @@ impl Scrubber {
             wrap: true,
         }
     }
+    /// A scrubber over a given list of images, such as a dataset
+    pub fn with_entries(entries: Vec<PathBuf>, path: &Path) -> Self {
+        let index = entries.iter().position(|p| p == path).unwrap_or_default();
+        Self {
+            index,
+            entries,
+            wrap: true,
+        }
+    }
+
     pub fn next(&mut self) -> PathBuf {
         self.index += 1;
         if self.index > self.entries.len().saturating_sub(1) {
@@ -56,11 +69,22 @@ impl Scrubber {
     pub fn len(&mut self) -> usize {
         self.entries.len()
     }
+
+    /// Only keep the entries in `matches`. The current image is kept so the index stays valid.
+    pub fn restrict(&mut self, matches: &[PathBuf]) {
+        let current = self.entries.get(self.index).cloned();
+        let matches: HashSet<&PathBuf> = matches.iter().collect();
+        self.entries
+            .retain(|p| matches.contains(p) || Some(p) == current.as_ref());
+        self.index = current
+            .and_then(|c| self.entries.iter().position(|p| *p == c))
+            .unwrap_or_default();
+    }
 }
 
 // Get sorted list of files in a folder
 // TODO: Should probably return an Result<T,E> instead, but am too lazy to figure out + handle a dedicated error type here
-// TODO: Cache this result, instead of doing it each time we need to fetch another file from the folder
+// The result is cached and kept up to date by `FolderIndex`, so this only runs once per folder
 pub fn get_image_filenames_for_directory(folder_path: &Path) -> Result<Vec<PathBuf>> {
     let mut folder_path = folder_path.to_path_buf();
     if folder_path.is_file() {
@@ -71,11 +95,12 @@ pub fn get_image_filenames_for_directory(folder_path: &Path) -> Result<Vec<PathB
     }
     let info = std::fs::read_dir(folder_path)?;
 
-    // TODO: Are symlinks handled correctly?
+    // `is_file` follows symlinks, so linked images are kept under the name of the link,
+    // while broken links and folders named like images are skipped
     let mut dir_files = info
         .flat_map(|x| x)
         .map(|x| x.path())
-        .filter(|x| is_ext_compatible(x))
+        .filter(|x| is_ext_compatible(x) && x.is_file())
         .collect::<Vec<PathBuf>>();
 
     dir_files.sort_unstable_by(|a, b| {
@@ -92,6 +117,48 @@ pub fn get_image_filenames_for_directory(folder_path: &Path) -> Result<Vec<PathB
     return Ok(dir_files);
 }
 
+/// Get sorted list of the images in a folder and all folders below it. Symlinked folders are
+/// followed, but each folder is only read once so links to a parent don't loop forever.
+/// Hidden folders, like the backups of batch operations, are skipped.
+pub fn get_image_filenames_recursive(folder_path: &Path) -> Result<Vec<PathBuf>> {
+    if !folder_path.is_dir() {
+        bail!("{} is not a folder", folder_path.display());
+    }
+    let mut folders = vec![folder_path.to_path_buf()];
+    let mut visited = HashSet::new();
+    let mut files = vec![];
+    while let Some(folder) = folders.pop() {
+        if !visited.insert(folder.canonicalize().unwrap_or_else(|_| folder.clone())) {
+            continue;
+        }
+        let info = match std::fs::read_dir(&folder) {
+            Ok(info) => info,
+            Err(e) => {
+                debug!("Can't read {}: {e}", folder.display());
+                continue;
+            }
+        };
+        for path in info.flatten().map(|x| x.path()) {
+            let hidden = path
+                .file_name()
+                .map(|n| n.to_string_lossy().starts_with('.'))
+                .unwrap_or_default();
+            if path.is_dir() {
+                if !hidden {
+                    folders.push(path);
+                }
+            } else if path.is_file() && is_ext_compatible(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort_unstable_by(|a, b| {
+        lexical_sort::natural_lexical_cmp(&a.to_string_lossy(), &b.to_string_lossy())
+    });
+    Ok(files)
+}
+
 /// Find first valid image from the directory
 /// Assumes the given path is a directory and not a file
 pub fn find_first_image_in_directory(folder_path: &PathBuf) -> Result<PathBuf> {
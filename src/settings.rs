@@ -47,6 +47,44 @@ pub struct PersistentSettings {
     pub svg_scale: f32,
     pub zen_mode: bool,
     pub theme: ColorTheme,
+    /// Colour annotations by their object id instead of their class
+    pub color_by_track: bool,
+    /// Assign object ids by matching with the previous image before annotations are saved
+    pub auto_track_ids: bool,
+    /// Place the opposite corner of a new box with a second click instead of dragging
+    pub two_click_drawing: bool,
+    /// Aspect ratio (width / height) of new boxes while shift is held
+    pub draw_aspect_ratio: f32,
+    /// Show the class, confidence and track of each box in the image
+    pub show_annotation_tags: bool,
+    /// Boxes smaller than this on screen (in points) get no tag
+    pub annotation_tag_min_size: f32,
+    /// Opacity of all boxes, multiplied with the opacity of their class
+    pub annotation_opacity: f32,
+    /// Space around a box when zooming to it, relative to its size
+    pub zoom_padding: f32,
+    /// Continue reviewing boxes on the next image after the last box
+    pub review_across_images: bool,
+    /// Show a magnified view of the cursor while drawing or resizing boxes
+    pub show_loupe: bool,
+    pub loupe_zoom: f32,
+    /// Keep box corners on whole pixels
+    pub snap_to_pixels: bool,
+    /// Snap box edges to the image border and to the edges of other boxes
+    pub snap_to_edges: bool,
+    /// Smallest width and height of a box, in image pixels
+    pub min_box_size: f32,
+    /// Smallest width and height of a new box on screen, in points
+    pub min_box_screen_size: f32,
+    /// Show the position in the folder and the annotation progress at the bottom
+    pub show_status_bar: bool,
+    /// Show the folder as a grid of thumbnails
+    pub show_thumbnails: bool,
+    /// Size of the tiles in the thumbnail grid, in points
+    pub thumbnail_size: f32,
+    /// Lock the labels of the open image with a sidecar file, for folders shared by
+    /// several annotators
+    pub lock_files: bool,
 }
 
 impl Default for PersistentSettings {
@@ -73,6 +111,25 @@ impl Default for PersistentSettings {
             svg_scale: 1.0,
             zen_mode: false,
             theme: ColorTheme::Dark,
+            color_by_track: false,
+            auto_track_ids: false,
+            two_click_drawing: false,
+            draw_aspect_ratio: 1.0,
+            show_annotation_tags: true,
+            annotation_tag_min_size: 24.0,
+            annotation_opacity: 1.0,
+            zoom_padding: 0.5,
+            review_across_images: true,
+            show_loupe: true,
+            loupe_zoom: 8.0,
+            snap_to_pixels: false,
+            snap_to_edges: false,
+            min_box_size: 2.0,
+            min_box_screen_size: 4.0,
+            show_status_bar: true,
+            show_thumbnails: false,
+            thumbnail_size: 96.0,
+            lock_files: false,
         }
     }
 }
@@ -81,7 +138,9 @@ impl PersistentSettings {
     pub fn load() -> Result<Self> {
         let local_dir = dirs::data_local_dir().ok_or(anyhow!("Can't get local dir"))?;
         let f = File::open(local_dir.join(".oculante"))?;
-        Ok(serde_json::from_reader::<_, PersistentSettings>(f)?)
+        let mut settings = serde_json::from_reader::<_, PersistentSettings>(f)?;
+        settings.shortcuts.add_missing_defaults();
+        Ok(settings)
     }
 
     // save settings in a thread so we don't block
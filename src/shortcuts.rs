@@ -34,6 +34,44 @@ pub enum InputEvent {
     PanUp,
     PanDown,
     DeleteAnnoation,
+    SetKeyframe,
+    InterpolateKeyframe,
+    AssignTrackIds,
+    StampMode,
+    SelectTool,
+    DrawTool,
+    PanTool,
+    NextUnannotated,
+    NextEmptyLabels,
+    NextWithClass,
+    NextFlagged,
+    NextFiltered,
+    PreviousFiltered,
+    AnnotationTags,
+    ThumbnailGrid,
+    HideAnnotations,
+    ZoomToAnnotation,
+    ReviewNext,
+    ReviewPrevious,
+    SelectNextAnnotation,
+    SelectPreviousAnnotation,
+    DeselectAnnotation,
+    MoveAnnotationLeft,
+    MoveAnnotationRight,
+    MoveAnnotationUp,
+    MoveAnnotationDown,
+    MoveAnnotationLeftFast,
+    MoveAnnotationRightFast,
+    MoveAnnotationUpFast,
+    MoveAnnotationDownFast,
+    GrowAnnotationLeft,
+    GrowAnnotationRight,
+    GrowAnnotationUp,
+    GrowAnnotationDown,
+    ShrinkAnnotationLeft,
+    ShrinkAnnotationRight,
+    ShrinkAnnotationUp,
+    ShrinkAnnotationDown,
     Copy,
     Paste,
     Browse,
@@ -41,6 +79,33 @@ pub enum InputEvent {
     ZenMode,
 }
 
+impl InputEvent {
+    /// Commands which edit the selected annotation. While an annotation is selected,
+    /// they take precedence over other commands bound to the same keys.
+    pub const ANNOTATION_EDITS: [InputEvent; 16] = [
+        InputEvent::MoveAnnotationLeft,
+        InputEvent::MoveAnnotationRight,
+        InputEvent::MoveAnnotationUp,
+        InputEvent::MoveAnnotationDown,
+        InputEvent::MoveAnnotationLeftFast,
+        InputEvent::MoveAnnotationRightFast,
+        InputEvent::MoveAnnotationUpFast,
+        InputEvent::MoveAnnotationDownFast,
+        InputEvent::GrowAnnotationLeft,
+        InputEvent::GrowAnnotationRight,
+        InputEvent::GrowAnnotationUp,
+        InputEvent::GrowAnnotationDown,
+        InputEvent::ShrinkAnnotationLeft,
+        InputEvent::ShrinkAnnotationRight,
+        InputEvent::ShrinkAnnotationUp,
+        InputEvent::ShrinkAnnotationDown,
+    ];
+
+    pub fn is_annotation_edit(&self) -> bool {
+        InputEvent::ANNOTATION_EDITS.contains(self)
+    }
+}
+
 pub type Shortcuts = BTreeMap<InputEvent, SimultaneousKeypresses>;
 
 pub type SimultaneousKeypresses = BTreeSet<String>;
@@ -68,6 +133,11 @@ pub trait ShortcutExt {
     {
         unimplemented!()
     }
+
+    /// Bind commands added since the shortcuts were saved to their default keys
+    fn add_missing_defaults(&mut self) {
+        unimplemented!()
+    }
 }
 
 pub trait KeyTrait {
@@ -121,12 +191,51 @@ impl ShortcutExt for Shortcuts {
             .add_key(InputEvent::Label9, "Key9")
             .add_key(InputEvent::ZenMode, "Z")
             .add_key(InputEvent::DeleteAnnoation, "Delete")
+            .add_key(InputEvent::SetKeyframe, "K")
+            .add_keys(InputEvent::InterpolateKeyframe, &["LShift", "K"])
+            .add_key(InputEvent::AssignTrackIds, "J")
+            .add_key(InputEvent::StampMode, "S")
+            .add_key(InputEvent::SelectTool, "A")
+            .add_key(InputEvent::DrawTool, "D")
+            .add_key(InputEvent::PanTool, "P")
+            .add_key(InputEvent::NextUnannotated, "U")
+            .add_keys(InputEvent::NextEmptyLabels, &["LShift", "U"])
+            .add_key(InputEvent::NextWithClass, "M")
+            .add_key(InputEvent::NextFlagged, "G")
+            .add_key(InputEvent::NextFiltered, "PageDown")
+            .add_key(InputEvent::PreviousFiltered, "PageUp")
+            .add_key(InputEvent::AnnotationTags, "L")
+            .add_key(InputEvent::ThumbnailGrid, "O")
+            .add_key(InputEvent::HideAnnotations, "H")
+            .add_key(InputEvent::ZoomToAnnotation, "B")
+            .add_key(InputEvent::ReviewNext, "N")
+            .add_keys(InputEvent::ReviewPrevious, &["LShift", "N"])
+            .add_key(InputEvent::SelectNextAnnotation, "Tab")
+            .add_keys(InputEvent::SelectPreviousAnnotation, &["LShift", "Tab"])
+            .add_key(InputEvent::DeselectAnnotation, "Escape")
+            .add_key(InputEvent::MoveAnnotationLeft, "Left")
+            .add_key(InputEvent::MoveAnnotationRight, "Right")
+            .add_key(InputEvent::MoveAnnotationUp, "Up")
+            .add_key(InputEvent::MoveAnnotationDown, "Down")
+            .add_keys(InputEvent::MoveAnnotationLeftFast, &["LShift", "Left"])
+            .add_keys(InputEvent::MoveAnnotationRightFast, &["LShift", "Right"])
+            .add_keys(InputEvent::MoveAnnotationUpFast, &["LShift", "Up"])
+            .add_keys(InputEvent::MoveAnnotationDownFast, &["LShift", "Down"])
+            .add_keys(InputEvent::GrowAnnotationLeft, &["LControl", "Left"])
+            .add_keys(InputEvent::GrowAnnotationRight, &["LControl", "Right"])
+            .add_keys(InputEvent::GrowAnnotationUp, &["LControl", "Up"])
+            .add_keys(InputEvent::GrowAnnotationDown, &["LControl", "Down"])
+            .add_keys(InputEvent::ShrinkAnnotationLeft, &["LAlt", "Left"])
+            .add_keys(InputEvent::ShrinkAnnotationRight, &["LAlt", "Right"])
+            .add_keys(InputEvent::ShrinkAnnotationUp, &["LAlt", "Up"])
+            .add_keys(InputEvent::ShrinkAnnotationDown, &["LAlt", "Down"])
             // .add_key(InputEvent::Browse, "F1") // FIXME: As Shortcuts is a HashMap, only the newer key-sequence will be registered
             .add_keys(InputEvent::Browse, &["LControl", "O"])
-            .add_keys(InputEvent::PanRight, &["LShift", "Right"])
-            .add_keys(InputEvent::PanLeft, &["LShift", "Left"])
-            .add_keys(InputEvent::PanDown, &["LShift", "Down"])
-            .add_keys(InputEvent::PanUp, &["LShift", "Up"])
+            // Shift with the arrows moves the selected box fast
+            .add_keys(InputEvent::PanRight, &["LControl", "LShift", "Right"])
+            .add_keys(InputEvent::PanLeft, &["LControl", "LShift", "Left"])
+            .add_keys(InputEvent::PanDown, &["LControl", "LShift", "Down"])
+            .add_keys(InputEvent::PanUp, &["LControl", "LShift", "Up"])
             .add_keys(InputEvent::Paste, &["LControl", "V"])
             .add_keys(InputEvent::Copy, &["LControl", "C"]);
         #[cfg(target_os = "macos")]
@@ -151,6 +260,11 @@ impl ShortcutExt for Shortcuts {
         self.insert(function, keys.into_iter().map(|k| k.to_string()).collect());
         self
     }
+    fn add_missing_defaults(&mut self) {
+        for (function, keys) in Shortcuts::default_keys() {
+            self.entry(function).or_insert(keys);
+        }
+    }
 }
 
 pub fn key_pressed(app: &mut App, state: &mut OculanteState, command: InputEvent) -> bool {
@@ -176,6 +290,19 @@ pub fn key_pressed(app: &mut App, state: &mut OculanteState, command: InputEvent
     }
 
     if let Some(keys) = state.persistent_settings.shortcuts.get(&command) {
+        // While an annotation is selected, editing it takes over keys shared with other commands
+        if command.is_annotation_edit() {
+            if state.selected_bbox_id.is_none() {
+                return false;
+            }
+        } else if state.selected_bbox_id.is_some()
+            && InputEvent::ANNOTATION_EDITS
+                .iter()
+                .any(|edit| state.persistent_settings.shortcuts.get(edit) == Some(keys))
+        {
+            return false;
+        }
+
         // make sure the appropriate number of keys are down
         if app.keyboard.down.len() != keys.len() {
             if command != InputEvent::Fullscreen {
@@ -232,6 +359,7 @@ pub fn key_pressed(app: &mut App, state: &mut OculanteState, command: InputEvent
                     InputEvent::ZoomOut,
                 ]
                 .contains(&command)
+                    || command.is_annotation_edit()
                 {
                     for (dn, _) in &app.keyboard.down {
                         if format!("{:?}", dn) == key {
@@ -1,11 +1,19 @@
 use crate::image_loader::*;
 use cmd_lib::run_cmd;
 
-use crate::shortcuts::{keypresses_as_markdown, ShortcutExt, Shortcuts};
+use crate::shortcuts::{keypresses_as_markdown, InputEvent, ShortcutExt, Shortcuts};
 
 use super::*;
 use std::{fs::File, io::Write, path::PathBuf, time::Instant};
 
+/// An empty folder for a test. The process id keeps concurrent test runs apart.
+fn test_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("oculante_{name}_test_{}", std::process::id()));
+    _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
 #[test]
 fn load() {
     open_image(&PathBuf::from("tests/frstvisuals-lmV1g1UbdhQ-unsplash.jpg")).unwrap();
@@ -155,6 +163,24 @@ fn dump_shortcuts() {
     }
 }
 
+#[test]
+fn saved_shortcuts_get_new_defaults() {
+    let mut saved = Shortcuts::default().add_key(InputEvent::NextImage, "D");
+    saved.add_missing_defaults();
+    let defaults = Shortcuts::default_keys();
+    assert_eq!(saved.len(), defaults.len());
+    // Keys the user changed are kept
+    assert_eq!(saved[&InputEvent::NextImage], ["D".to_string()].into());
+    assert_eq!(
+        saved[&InputEvent::MoveAnnotationLeftFast],
+        defaults[&InputEvent::MoveAnnotationLeftFast]
+    );
+    assert_ne!(
+        defaults[&InputEvent::MoveAnnotationLeftFast],
+        defaults[&InputEvent::PanLeft]
+    );
+}
+
 #[test]
 fn bench_process_all() {
     std::env::set_var("RUST_LOG", "info");
@@ -199,3 +225,995 @@ fn bench_process_all() {
     }
     info!("{} ms mean", total / iters);
 }
+
+#[test]
+fn yolo_label_roundtrip() {
+    use crate::yolo_labels::{Label, Labels};
+    use std::convert::TryFrom;
+
+    let line = "2 0.5 0.25 0.1 0.2 0.9 7";
+    let label = Label::try_from(line).unwrap();
+    assert_eq!(label.object_id, Some(7));
+    assert_eq!(label.to_string(), line);
+    // Without a probability there is no column for the id
+    let label = Label {
+        probability: None,
+        ..label
+    };
+    assert_eq!(label.to_string(), "2 0.5 0.25 0.1 0.2");
+
+    let other = Label::try_from("2 0.7 0.25 0.3 0.2").unwrap();
+    let mid = Label::try_from("2 0.5 0.25 0.1 0.2")
+        .unwrap()
+        .lerp(&other, 0.5);
+    assert!((mid.x_centre - 0.6).abs() < 1e-6);
+    assert!((mid.width - 0.2).abs() < 1e-6);
+    assert!(mid.interpolated);
+
+    // Class ids aren't limited to a byte
+    let label = Label::try_from("300 0.5 0.25 0.1 0.2").unwrap();
+    assert_eq!(label.label_index, 300);
+    assert_eq!(label.to_string(), "300 0.5 0.25 0.1 0.2");
+
+    // Malformed and truncated lines are errors, not panics
+    assert!(Label::try_from("not a label").is_err());
+    assert!(Label::try_from("2 0.5 0.25").is_err());
+    assert!(Label::try_from("2 0.5 0.25 0.1 0.2 high").is_err());
+    let error = Labels::try_from("2 0.5 0.25 0.1 0.2\r\n2 0.5 x 0.1 0.2\n")
+        .err()
+        .unwrap();
+    assert!(format!("{error:#}").starts_with("Line 2"));
+    assert_eq!(
+        Labels::try_from("2 0.5 0.25 0.1 0.2\r\n\n").unwrap().len(),
+        1
+    );
+}
+
+#[test]
+fn keyframe_interpolation() {
+    use crate::appstate::{AnnoationBoundingBox, OculanteState};
+    use crate::scrubber::Scrubber;
+    use crate::tracks::{interpolate_from_keyframe, read_labels_for_image, Keyframe};
+    use crate::yolo_labels::tracks_filename_for;
+
+    let folder = test_folder("interpolation");
+    let images: Vec<PathBuf> = (0..3).map(|i| folder.join(format!("{i}.png"))).collect();
+    // The keyframe is found by its object id, not by its place in the file
+    std::fs::write(
+        folder.join("0.txt"),
+        "1 0.9 0.9 0.1 0.1\n0 0.2 0.5 0.1 0.1 0.8 4",
+    )
+    .unwrap();
+
+    let mut state = OculanteState {
+        current_path: Some(images[2].clone()),
+        image_dimension: (100, 100),
+        is_loaded: true,
+        annotation_bboxes: vec![AnnoationBoundingBox::from_center(80., 50., 10., 10., 0)],
+        selected_bbox_id: Some(0),
+        keyframe: Some(Keyframe {
+            image_path: images[0].clone(),
+            object_id: 4,
+        }),
+        scrubber: Scrubber {
+            index: 2,
+            entries: images.clone(),
+            wrap: false,
+        },
+        ..Default::default()
+    };
+    interpolate_from_keyframe(&mut state);
+    assert_eq!(state.annotation_bboxes[0].object_id, Some(4));
+
+    let labels = read_labels_for_image(&images[1]);
+    assert_eq!(labels.len(), 1);
+    assert_eq!(labels[0].object_id, Some(4));
+    assert!(labels[0].interpolated);
+    assert!((labels[0].x_centre - 0.5).abs() < 1e-6);
+    // The label file keeps the standard columns, the track file the rest
+    let labels_file = folder.join("1.txt");
+    assert_eq!(
+        std::fs::read_to_string(&labels_file).unwrap(),
+        labels[0].to_string()
+    );
+    assert!(tracks_filename_for(&labels_file).exists());
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn track_operations() {
+    use crate::lock::{lock_filename_for, LabelLock};
+    use crate::tracks::{merge_tracks, read_labels_for_image, renumber_tracks};
+
+    let folder = test_folder("track_operations");
+    let images: Vec<PathBuf> = (0..3).map(|i| folder.join(format!("{i}.png"))).collect();
+    for (i, image) in images.iter().enumerate() {
+        std::fs::write(
+            image.with_extension("txt"),
+            format!("0 0.5 0.5 0.1 0.1 0.8 {}", i + 5),
+        )
+        .unwrap();
+    }
+    let theirs = LabelLock {
+        user: "someone".into(),
+        host: "elsewhere".into(),
+        ..Default::default()
+    };
+    let lock_file = lock_filename_for(&images[1]);
+    std::fs::write(&lock_file, serde_json::to_string(&theirs).unwrap()).unwrap();
+
+    // The image someone else is editing is left alone and reported
+    let rewritten = merge_tracks(&images, 6, 5).unwrap();
+    assert_eq!(rewritten.num_changed, 0);
+    assert_eq!(rewritten.skipped, vec![images[1].clone()]);
+    assert_eq!(read_labels_for_image(&images[1])[0].object_id, Some(6));
+
+    std::fs::write(images[2].with_extension("txt"), "not a label").unwrap();
+    let rewritten = renumber_tracks(&images).unwrap();
+    assert_eq!(rewritten.num_changed, 1);
+    assert_eq!(
+        rewritten.skipped,
+        vec![images[1].clone(), images[2].clone()]
+    );
+
+    std::fs::remove_file(&lock_file).unwrap();
+    let rewritten = renumber_tracks(&images).unwrap();
+    assert_eq!(rewritten.num_changed, 1);
+    assert_eq!(read_labels_for_image(&images[0])[0].object_id, Some(0));
+    assert_eq!(read_labels_for_image(&images[1])[0].object_id, Some(1));
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn draw_modifiers() {
+    use crate::appstate::DrawModifiers;
+    use nalgebra::Vector2;
+
+    let start = Vector2::new(10., 10.);
+    let cursor = Vector2::new(20., 6.);
+
+    let square = DrawModifiers {
+        from_center: false,
+        aspect_ratio: Some(1.0),
+    };
+    assert_eq!(
+        square.corner_points(start, cursor),
+        (start, Vector2::new(20., 0.))
+    );
+
+    let centered = DrawModifiers {
+        from_center: true,
+        aspect_ratio: None,
+    };
+    assert_eq!(
+        centered.corner_points(start, cursor),
+        (Vector2::new(0., 14.), cursor)
+    );
+}
+
+#[test]
+fn annotation_sort() {
+    use crate::appstate::{AnnoationBoundingBox, AnnotationColumn, AnnotationSort};
+
+    let bboxes = vec![
+        AnnoationBoundingBox::from_center(50., 50., 10., 30., 2),
+        AnnoationBoundingBox::from_center(20., 50., 40., 20., 0),
+        AnnoationBoundingBox::from_center(80., 50., 20., 10., 1),
+    ];
+
+    let mut sort = AnnotationSort::default();
+    assert_eq!(sort.order(&bboxes), vec![0, 1, 2]);
+
+    sort.toggle(AnnotationColumn::Class);
+    assert_eq!(sort.order(&bboxes), vec![1, 2, 0]);
+
+    sort.toggle(AnnotationColumn::Class);
+    assert_eq!(sort.order(&bboxes), vec![0, 2, 1]);
+
+    sort.toggle(AnnotationColumn::Width);
+    assert_eq!(sort.order(&bboxes), vec![0, 2, 1]);
+}
+
+#[test]
+fn round_to_pixels() {
+    use crate::appstate::AnnoationBoundingBox;
+    use nalgebra::Vector2;
+
+    let mut bbox = AnnoationBoundingBox::from_center(10.3, 20.6, 5.2, 3.3, 0);
+    bbox.round_to_pixels();
+    assert_eq!(bbox.tl_corner(), Vector2::new(8., 19.));
+    assert_eq!(bbox.br_corner(), Vector2::new(13., 22.));
+}
+
+#[test]
+fn clamp_to_image() {
+    use crate::appstate::AnnoationBoundingBox;
+    use nalgebra::Vector2;
+
+    let mut bbox = AnnoationBoundingBox::from_center(2., 95., 10., 20., 0);
+    bbox.clamp_to_image(100., 100.);
+    assert_eq!(bbox.tl_corner(), Vector2::new(0., 80.));
+    assert_eq!(bbox.br_corner(), Vector2::new(10., 100.));
+
+    let mut bbox = AnnoationBoundingBox::from_center(50., 50., 150., 20., 0);
+    bbox.clamp_to_image(100., 100.);
+    assert_eq!(bbox.tl_corner(), Vector2::new(0., 40.));
+    assert_eq!(bbox.br_corner(), Vector2::new(100., 60.));
+}
+
+#[test]
+fn snap_targets() {
+    use crate::appstate::{AnnoationBoundingBox, SnapGuides, SnapTargets};
+    use nalgebra::Vector2;
+
+    let targets = SnapTargets {
+        xs: vec![0., 100.],
+        ys: vec![0., 50.],
+        distance: 3.,
+    };
+
+    let (p, guides) = targets.snap_point(Vector2::new(98., 20.));
+    assert_eq!(p, Vector2::new(100., 20.));
+    assert_eq!(
+        guides,
+        SnapGuides {
+            x: Some(100.),
+            y: None
+        }
+    );
+
+    // The right edge is at 102 and closer to a line than the left edge at 82
+    let bbox = AnnoationBoundingBox::from_center(92., 30., 20., 10., 0);
+    let (offset, guides) = targets.snap_box(&bbox);
+    assert_eq!(offset, Vector2::new(-2., 0.));
+    assert_eq!(guides.x, Some(100.));
+}
+
+#[test]
+fn locked_classes() {
+    use crate::appstate::{AnnoationBoundingBox, OculanteState};
+    use crate::utils::{delete_selected_annotation, set_label_class};
+
+    let mut state = OculanteState {
+        annotation_bboxes: vec![AnnoationBoundingBox::from_center(50., 50., 10., 10., 0)],
+        selected_bbox_id: Some(0),
+        ..Default::default()
+    };
+    state.annotation_visibility.class_mut(0).locked = true;
+    set_label_class(&mut state, 1);
+    assert_eq!(state.current_label_class, 1);
+    assert_eq!(state.annotation_bboxes[0].class_id, 0);
+    delete_selected_annotation(&mut state);
+    assert_eq!(state.annotation_bboxes.len(), 1);
+
+    state.annotation_visibility.class_mut(0).locked = false;
+    set_label_class(&mut state, 1);
+    assert_eq!(state.annotation_bboxes[0].class_id, 1);
+    delete_selected_annotation(&mut state);
+    assert!(state.annotation_bboxes.is_empty());
+}
+
+#[test]
+fn click_topmost_box() {
+    use crate::appstate::{AnnoationBoundingBox, BoundingBoxEditMode, OculanteState};
+    use crate::utils::{annotation_at, reorder_annotation};
+    use nalgebra::Vector2;
+
+    let mut state = OculanteState {
+        annotation_bboxes: vec![
+            AnnoationBoundingBox::from_center(50., 50., 40., 40., 0),
+            AnnoationBoundingBox::from_center(50., 50., 20., 20., 1),
+        ],
+        ..Default::default()
+    };
+    let centre = Vector2::new(50., 50.);
+    let hit = |state: &OculanteState| {
+        let part = BoundingBoxEditMode::None
+            .get_part_element(
+                centre,
+                &state.annotation_bboxes,
+                &state.annotation_visibility,
+            )
+            .map(|element| element.id);
+        assert_eq!(part, annotation_at(state, centre));
+        state.annotation_bboxes[part.unwrap()].class_id
+    };
+    // The box drawn last is on top
+    assert_eq!(hit(&state), 1);
+    reorder_annotation(&mut state, 0, true);
+    assert_eq!(hit(&state), 0);
+}
+
+#[test]
+fn discard_tiny_boxes() {
+    use crate::appstate::{AnnoationBoundingBox, BoundingBoxEditMode, BoundingBoxPart};
+    use nalgebra::Vector2;
+
+    let mut bboxes = vec![AnnoationBoundingBox::from_center(10., 10., 1.5, 20., 0)];
+    let mut edit_mode = BoundingBoxEditMode::New {
+        id: 0,
+        start_point: Some(Vector2::new(9.25, 0.)),
+        awaiting_second_click: false,
+    };
+    edit_mode.mouse_button_up(Vector2::new(10.75, 20.), &mut bboxes, 2., (100, 100));
+    assert!(bboxes.is_empty());
+
+    bboxes.push(AnnoationBoundingBox::from_center(10., 10., 3., 20., 0));
+    edit_mode = BoundingBoxEditMode::New {
+        id: 0,
+        start_point: Some(Vector2::new(8.5, 0.)),
+        awaiting_second_click: false,
+    };
+    edit_mode.mouse_button_up(Vector2::new(11.5, 20.), &mut bboxes, 2., (100, 100));
+    assert_eq!(bboxes.len(), 1);
+
+    // A box resized below the minimum is grown back instead of removed
+    bboxes[0] = AnnoationBoundingBox::from_center(10., 10., 0.5, 20., 0);
+    edit_mode = BoundingBoxEditMode::DragEdge {
+        id: 0,
+        part: BoundingBoxPart::EdgeLeft,
+    };
+    edit_mode.mouse_button_up(Vector2::new(9.75, 10.), &mut bboxes, 2., (100, 100));
+    assert_eq!(bboxes[0].size(), (2., 20.));
+    assert_eq!(bboxes[0].center(), Vector2::new(10., 10.));
+
+    // Growing it back keeps it inside the image
+    bboxes[0] = AnnoationBoundingBox::from_center(0.25, 10., 0.5, 20., 0);
+    edit_mode = BoundingBoxEditMode::DragEdge {
+        id: 0,
+        part: BoundingBoxPart::EdgeRight,
+    };
+    edit_mode.mouse_button_up(Vector2::new(0.5, 10.), &mut bboxes, 2., (100, 100));
+    assert_eq!(bboxes[0].size(), (2., 20.));
+    assert_eq!(bboxes[0].center(), Vector2::new(1., 10.));
+}
+
+#[test]
+fn find_filtered_image() {
+    use crate::image_filter::{find_image, ImageFilter};
+
+    let entries: Vec<PathBuf> = (0..4).map(|i| PathBuf::from(format!("{i}.png"))).collect();
+
+    assert_eq!(
+        find_image(&entries, None, 1, true, false, ImageFilter::All, 1.),
+        Some(2)
+    );
+    assert_eq!(
+        find_image(&entries, None, 3, true, false, ImageFilter::All, 1.),
+        None
+    );
+    assert_eq!(
+        find_image(&entries, None, 3, true, true, ImageFilter::All, 1.),
+        Some(0)
+    );
+    assert_eq!(
+        find_image(&entries, None, 0, false, false, ImageFilter::All, 1.),
+        None
+    );
+    assert_eq!(
+        find_image(&entries, None, 0, false, true, ImageFilter::All, 1.),
+        Some(3)
+    );
+    assert_eq!(
+        find_image(&[], None, 0, true, true, ImageFilter::All, 1.),
+        None
+    );
+}
+
+#[test]
+fn dataset_query() {
+    use crate::query::{evaluate_query, run_query};
+
+    let folder = test_folder("query");
+    let image = folder.join("image.png");
+    std::fs::write(
+        folder.join("image.txt"),
+        "2 0.5 0.5 0.1 0.1\n2 0.2 0.2 0.1 0.1\n0 0.8 0.8 0.2 0.2\n7 0.1 0.1 0.05 0.05",
+    )
+    .unwrap();
+    let unlabelled = folder.join("unlabelled.png");
+
+    assert!(evaluate_query("count(class == 2) == 2", &image, 8).unwrap());
+    assert!(evaluate_query("count(class == 2) > 1 && boxes == 4", &image, 8).unwrap());
+    assert!(evaluate_query("any(w > 0.15) && !all(class == 2)", &image, 8).unwrap());
+    assert!(evaluate_query("has_unknown_class", &image, 3).unwrap());
+    assert!(!evaluate_query("has_unknown_class", &image, 8).unwrap());
+    assert!(evaluate_query("classes == 3", &image, 8).unwrap());
+    assert!(evaluate_query("!has_labels && boxes == 0", &unlabelled, 8).unwrap());
+    assert!(evaluate_query("count(class ==", &image, 8).is_err());
+    assert!(evaluate_query("boxes", &image, 8).is_err());
+
+    let matches = run_query("boxes > 0", &[image.clone(), unlabelled], 8).unwrap();
+    assert_eq!(matches, vec![image]);
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn batch_operations() {
+    use crate::batch::{apply_batch, plan_batch, revert_batch, BatchAction};
+    use crate::lock::{lock_filename_for, LabelLock};
+    use crate::yolo_labels::{Label, Labels};
+    use std::convert::TryFrom;
+
+    let expanded = BatchAction::Expand(100.)
+        .apply(&Label::try_from("1 0.05 0.5 0.1 0.2").unwrap())
+        .unwrap();
+    assert!((expanded.width - 0.15).abs() < 1e-6);
+    assert!((expanded.x_centre - 0.075).abs() < 1e-6);
+    assert!((expanded.height - 0.4).abs() < 1e-6);
+
+    let folder = test_folder("batch");
+    let images: Vec<PathBuf> = (0..3).map(|i| folder.join(format!("{i}.png"))).collect();
+    let original = "3 0.5 0.5 0.1 0.1 0.9\n0 0.2 0.2 0.1 0.1 0.2";
+    std::fs::write(folder.join("0.txt"), original).unwrap();
+    std::fs::write(folder.join("1.txt"), "0 0.5 0.5 0.1 0.1").unwrap();
+
+    let plan = plan_batch("conf < 0.3", BatchAction::Delete, &images).unwrap();
+    assert_eq!(plan.changes.len(), 1);
+    assert_eq!(plan.num_boxes(), 1);
+    assert!(plan_batch("class ==", BatchAction::Delete, &images).is_err());
+
+    let plan = plan_batch("class == 3", BatchAction::SetClass(5), &images).unwrap();
+    let applied = apply_batch(&plan, &folder).unwrap();
+    assert_eq!((applied.num_images, applied.num_boxes), (1, 1));
+    let labels = std::fs::read_to_string(folder.join("0.txt")).unwrap();
+    assert!(labels.starts_with("5 0.5 0.5"));
+    assert!(!folder.join("0.txt.batch").exists());
+
+    let reverted = revert_batch(&applied.backup_folder).unwrap();
+    assert_eq!(reverted.restored, 1);
+    assert!(reverted.edited.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(folder.join("0.txt")).unwrap(),
+        original
+    );
+
+    // Track files are restored with the labels, and removed if the batch created them
+    let tracks = |name: &str| folder.join(name).with_extension("tracks");
+    let mut labels = Labels::try_from(original).unwrap();
+    labels.labels[0].object_id = Some(4);
+    labels.to_file(folder.join("0.txt")).unwrap();
+    let plan = plan_batch("class == 3", BatchAction::SetClass(5), &images).unwrap();
+    let tracks_before = std::fs::read_to_string(tracks("0")).unwrap();
+    let applied = apply_batch(&plan, &folder).unwrap();
+    std::fs::remove_file(tracks("0")).unwrap();
+    revert_batch(&applied.backup_folder).unwrap();
+    assert_eq!(std::fs::read_to_string(tracks("0")).unwrap(), tracks_before);
+    std::fs::write(folder.join("0.txt"), original).unwrap();
+    std::fs::remove_file(tracks("0")).unwrap();
+
+    // Label files edited after the batch are kept
+    let plan = plan_batch("class == 3", BatchAction::SetClass(5), &images).unwrap();
+    let applied = apply_batch(&plan, &folder).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    std::fs::write(folder.join("0.txt"), "1 0.5 0.5 0.2 0.2").unwrap();
+    let reverted = revert_batch(&applied.backup_folder).unwrap();
+    assert_eq!(reverted.restored, 0);
+    assert_eq!(reverted.edited, vec![folder.join("0.txt")]);
+    assert_eq!(
+        std::fs::read_to_string(folder.join("0.txt")).unwrap(),
+        "1 0.5 0.5 0.2 0.2"
+    );
+
+    // Images someone else is editing are left alone
+    std::fs::write(folder.join("0.txt"), original).unwrap();
+    let theirs = LabelLock {
+        user: "someone".into(),
+        host: "elsewhere".into(),
+        ..Default::default()
+    };
+    std::fs::write(
+        lock_filename_for(&images[0]),
+        serde_json::to_string(&theirs).unwrap(),
+    )
+    .unwrap();
+    let applied = apply_batch(&plan, &folder).unwrap();
+    assert_eq!(applied.num_images, 0);
+    assert_eq!(applied.locked, vec![images[0].clone()]);
+    assert_eq!(
+        std::fs::read_to_string(folder.join("0.txt")).unwrap(),
+        original
+    );
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn thumbnails_and_status() {
+    use crate::project::{ImageStatus, ProjectSettings};
+    use crate::thumbnails::{load_thumbnail, THUMBNAIL_SIZE};
+
+    let thumbnail =
+        load_thumbnail(&PathBuf::from("tests/frstvisuals-lmV1g1UbdhQ-unsplash.jpg")).unwrap();
+    assert_eq!(thumbnail.width().max(thumbnail.height()), THUMBNAIL_SIZE);
+
+    let folder = PathBuf::from("dataset");
+    let image = folder.join("a.png");
+    let mut project = ProjectSettings::default();
+    assert_eq!(
+        project.image_status(&folder, &image),
+        ImageStatus::Unvisited
+    );
+    project.set_image_status(&folder, &image, ImageStatus::Done);
+    assert_eq!(project.image_status(&folder, &image), ImageStatus::Done);
+    assert!(project.image_status.contains_key("a.png"));
+    project.set_image_status(&folder, &image, ImageStatus::Unvisited);
+    assert!(project.image_status.is_empty());
+
+    // Statuses saved by someone else in the meantime are kept
+    let folder = test_folder("status");
+    let mut theirs = ProjectSettings::default();
+    let mut mine = theirs.clone();
+    theirs
+        .save_image_status(&folder, &[folder.join("a")], ImageStatus::Done)
+        .unwrap();
+    mine.save_image_status(&folder, &[folder.join("b")], ImageStatus::Visited)
+        .unwrap();
+    let saved = ProjectSettings::load(&folder).unwrap();
+    assert_eq!(
+        saved.image_status(&folder, &folder.join("a")),
+        ImageStatus::Done
+    );
+    assert_eq!(
+        saved.image_status(&folder, &folder.join("b")),
+        ImageStatus::Visited
+    );
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn progress_counts() {
+    use crate::progress::ProgressCounter;
+    use crate::project::{ImageStatus, ProjectSettings};
+    use crate::scrubber::get_image_filenames_for_directory;
+    use crate::utils::group_digits;
+
+    assert_eq!(group_digits(7), "7");
+    assert_eq!(group_digits(2000), "2 000");
+    assert_eq!(group_digits(1234567), "1 234 567");
+
+    let folder = test_folder("progress");
+    for i in 0..4 {
+        std::fs::write(folder.join(format!("{i}.png")), "").unwrap();
+    }
+    std::fs::write(folder.join("0.txt"), "0 0.5 0.5 0.1 0.1").unwrap();
+    let mut project = ProjectSettings::default();
+    project.set_image_status(&folder, &folder.join("1.png"), ImageStatus::Background);
+
+    let mut progress = ProgressCounter::new(
+        Some(&folder),
+        get_image_filenames_for_directory(&folder).unwrap(),
+    );
+    assert!(!progress.counts.is_complete());
+    progress.update(&project, None);
+    assert!(progress.counts.is_complete());
+    assert_eq!(progress.counts.images, 4);
+    assert_eq!(progress.counts.annotated, 1);
+    assert_eq!(progress.counts.background, 1);
+
+    progress.set_boxes(&folder.join("1.png"), 2);
+    progress.set_status(&folder.join("2.png"), ImageStatus::Done);
+    assert_eq!(progress.counts.annotated, 3);
+    assert_eq!(progress.counts.background, 0);
+    assert_eq!(progress.counts.fraction(), 0.75);
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn recursive_and_list_datasets() {
+    use crate::dataset::{DataYaml, Dataset, DatasetSource};
+    use crate::scrubber::get_image_filenames_recursive;
+    use crate::utils::labels_filename_for;
+    use std::convert::TryFrom;
+
+    let data = DataYaml::try_from(
+        "# dataset\npath: ../data\ntrain: [images/a, 'images/b']\nval:\n  - images/val # checked\n\
+         test:\nnames:\n  0: person\n  1: 'car'\n",
+    )
+    .unwrap();
+    assert_eq!(data.path, Some("../data".into()));
+    assert_eq!(data.splits["train"], vec!["images/a", "images/b"]);
+    assert_eq!(data.splits["val"], vec!["images/val"]);
+    assert!(data.splits["test"].is_empty());
+    assert_eq!(data.names, vec!["person", "car"]);
+    assert_eq!(
+        DataYaml::try_from("names: ['person', car]").unwrap().names,
+        vec!["person", "car"]
+    );
+    assert!(DataYaml::try_from("names: [é").is_err());
+
+    let folder = test_folder("dataset");
+    for sub in ["a/b", ".hidden"].iter() {
+        std::fs::create_dir_all(folder.join(sub)).unwrap();
+    }
+    for image in ["0.png", "a/1.png", "a/b/2.png", ".hidden/3.png"].iter() {
+        std::fs::write(folder.join(image), "").unwrap();
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&folder, folder.join("a/loop")).unwrap();
+
+    let entries = get_image_filenames_recursive(&folder).unwrap();
+    assert_eq!(
+        entries,
+        vec![
+            folder.join("0.png"),
+            folder.join("a/1.png"),
+            folder.join("a/b/2.png")
+        ]
+    );
+
+    std::fs::write(
+        folder.join("train.txt"),
+        "./a/1.png\n\n0.png\nmissing.txt\n",
+    )
+    .unwrap();
+    std::fs::write(
+        folder.join("data.yaml"),
+        "train: train.txt\nval: a/b\nnames: [x]",
+    )
+    .unwrap();
+    let list = Dataset::open(DatasetSource::list(&folder.join("train.txt"))).unwrap();
+    assert_eq!(list.entries.len(), 2);
+    let yaml = Dataset::open(DatasetSource::list(&folder.join("data.yaml"))).unwrap();
+    assert_eq!(yaml.entries.len(), 2);
+    assert_eq!(yaml.class_names, vec!["x"]);
+    let val = Dataset::open(DatasetSource::List {
+        file: folder.join("data.yaml"),
+        split: Some("val".into()),
+    })
+    .unwrap();
+    assert_eq!(val.entries, vec![folder.join("a/b/2.png")]);
+    assert_eq!(val.root(), folder);
+
+    // Labels of the Ultralytics layout are in a `labels` folder next to `images`
+    let image = folder.join("images/train/4.png");
+    assert_eq!(
+        labels_filename_for(&image),
+        folder.join("images/train/4.txt")
+    );
+    std::fs::create_dir(folder.join("labels")).unwrap();
+    assert_eq!(
+        labels_filename_for(&image),
+        folder.join("labels/train/4.txt")
+    );
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn folder_index() {
+    use crate::folder_index::{FolderIndex, IndexSource, LabelSummary};
+    use crate::image_filter::{find_image, ImageFilter};
+    use crate::project::SortOrder;
+    use crate::yolo_labels::Labels;
+    use std::convert::TryFrom;
+    use std::time::{Duration, Instant};
+
+    let folder = test_folder("index");
+    for i in 0..3 {
+        std::fs::write(folder.join(format!("{i}.png")), "").unwrap();
+    }
+    let labels = "2 0.5 0.5 0.1 0.1\n0 0.5 0.5 0.1 0.1 0.8 3";
+    std::fs::write(folder.join("1.txt"), labels).unwrap();
+    std::fs::write(folder.join("2.txt"), "").unwrap();
+
+    let wait_for = |index: &mut FolderIndex, done: &dyn Fn(&FolderIndex) -> bool| {
+        let start = Instant::now();
+        while !done(index) && start.elapsed() < Duration::from_secs(10) {
+            index.poll();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(done(index));
+    };
+
+    let mut index = FolderIndex::new(IndexSource::Folder(folder.clone()), SortOrder::Name);
+    assert!(index.will_cover(&folder.join("0.png")));
+    wait_for(&mut index, &|i| i.ready);
+    assert_eq!(index.entries.len(), 3);
+    assert!(index.covers(&folder.join("2.png")));
+    assert_eq!(index.labels[&folder.join("0.png")], LabelSummary::default());
+    assert_eq!(
+        index.labels[&folder.join("1.png")],
+        LabelSummary::new(&Labels::try_from(labels).unwrap())
+    );
+    assert_eq!(index.labels[&folder.join("1.png")].classes, vec![0, 2]);
+    assert_eq!(index.labels[&folder.join("1.png")].max_object_id, Some(3));
+    assert_eq!(index.labels[&folder.join("2.png")].boxes, 0);
+
+    let next = |filter| {
+        find_image(
+            &index.entries,
+            Some(&index.labels),
+            0,
+            true,
+            false,
+            filter,
+            1.,
+        )
+    };
+    assert_eq!(next(ImageFilter::WithClass(2)), Some(1));
+    assert_eq!(next(ImageFilter::EmptyLabels), Some(2));
+    assert_eq!(next(ImageFilter::Unannotated), None);
+
+    // New files are picked up by the watcher
+    std::fs::write(folder.join("3.png"), "").unwrap();
+    std::fs::write(folder.join("0.txt"), "1 0.5 0.5 0.1 0.1").unwrap();
+    wait_for(&mut index, &|i| {
+        i.entries.len() == 4 && i.labels.get(&folder.join("0.png")).map(|s| s.boxes) == Some(1)
+    });
+
+    // Label files edited in place are read again, and malformed ones don't stop the watcher
+    std::fs::write(folder.join("3.txt"), "not a label").unwrap();
+    std::fs::write(folder.join("1.txt"), "2 0.5 0.5 0.1 0.1").unwrap();
+    wait_for(&mut index, &|i| {
+        i.labels.get(&folder.join("1.png")).map(|s| s.boxes) == Some(1)
+    });
+    assert_eq!(index.entries.len(), 4);
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn sort_orders() {
+    use crate::folder_index::{FolderIndex, IndexSource};
+    use crate::project::{ProjectSettings, SortOrder};
+    use std::time::{Duration, Instant};
+
+    let folder = test_folder("sort");
+    // Names, sizes and box counts all give different orders
+    for (name, size, boxes) in [("a", 3, 1), ("b", 1, 2), ("c", 2, 0)].iter() {
+        std::fs::write(folder.join(format!("{name}.png")), vec![0; *size]).unwrap();
+        let labels = vec!["0 0.5 0.5 0.1 0.1"; *boxes].join("\n");
+        std::fs::write(folder.join(format!("{name}.txt")), labels).unwrap();
+    }
+
+    let sorted = |order| {
+        let mut index = FolderIndex::new(IndexSource::Folder(folder.clone()), order);
+        let start = Instant::now();
+        while !index.ready && start.elapsed() < Duration::from_secs(10) {
+            index.poll();
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        index
+            .entries
+            .iter()
+            .map(|p| p.file_stem().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(sorted(SortOrder::Name), vec!["a", "b", "c"]);
+    assert_eq!(sorted(SortOrder::Size), vec!["b", "c", "a"]);
+    assert_eq!(sorted(SortOrder::Boxes), vec!["c", "a", "b"]);
+
+    let shuffled = sorted(SortOrder::Shuffle(7));
+    assert_eq!(sorted(SortOrder::Shuffle(7)), shuffled);
+    let mut names = shuffled.clone();
+    names.sort();
+    assert_eq!(names, vec!["a", "b", "c"]);
+
+    let project = ProjectSettings {
+        sort_order: SortOrder::Shuffle(7),
+        ..Default::default()
+    };
+    project.save(&folder).unwrap();
+    assert_eq!(
+        ProjectSettings::load(&folder).unwrap().sort_order,
+        SortOrder::Shuffle(7)
+    );
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn reload_changed_labels() {
+    use crate::appstate::{AnnoationBoundingBox, OculanteState};
+    use crate::utils::{
+        check_label_file, has_unsaved_edits, load_annotations_from_file, merge_annotations,
+        merge_label_conflict, write_annotions_to_file,
+    };
+    use std::time::Duration;
+
+    let a = AnnoationBoundingBox::from_center(50., 50., 10., 10., 0);
+    let b = AnnoationBoundingBox::from_center(20., 20., 10., 10., 1);
+    let c = AnnoationBoundingBox::from_center(80., 20., 10., 10., 2);
+    let almost_a = AnnoationBoundingBox::from_center(50.2, 50., 10., 10., 0);
+    let moved_a = AnnoationBoundingBox::from_center(55., 50., 10., 10., 0);
+    // Boxes are told apart by their centres
+    let centers =
+        |boxes: &[AnnoationBoundingBox]| boxes.iter().map(|b| b.center()).collect::<Vec<_>>();
+    assert_eq!(
+        centers(&merge_annotations(&[], &[a], &[almost_a, b])),
+        centers(&[a, b])
+    );
+    assert_eq!(centers(&merge_annotations(&[], &[a], &[])), centers(&[a]));
+    // Deleted on either side stays deleted
+    assert_eq!(
+        centers(&merge_annotations(&[a, b], &[a], &[a, b, c])),
+        centers(&[a, c])
+    );
+    assert_eq!(
+        centers(&merge_annotations(&[a, b], &[a, b, c], &[a])),
+        centers(&[a, c])
+    );
+    // Changed on one side takes that change
+    assert_eq!(
+        centers(&merge_annotations(&[a, b], &[a, b], &[moved_a, b])),
+        centers(&[b, moved_a])
+    );
+    assert_eq!(
+        centers(&merge_annotations(&[a, b], &[moved_a, b], &[a, b])),
+        centers(&[moved_a, b])
+    );
+
+    let folder = test_folder("reload");
+    let labels_file = folder.join("0.txt");
+    // Give every write a new modification time
+    let write_labels = |text: &str| {
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(&labels_file, text).unwrap();
+    };
+    let boxes_on_disk = || {
+        std::fs::read_to_string(&labels_file)
+            .unwrap()
+            .lines()
+            .count()
+    };
+
+    let mut state = OculanteState {
+        current_path: Some(folder.join("0.png")),
+        image_dimension: (100, 100),
+        is_loaded: true,
+        ..Default::default()
+    };
+    write_labels("0 0.5 0.5 0.1 0.1");
+    load_annotations_from_file(&mut state);
+    assert_eq!(state.annotation_bboxes.len(), 1);
+    assert!(!has_unsaved_edits(&state));
+
+    // Without edits, changes on disk are loaded
+    write_labels("0 0.5 0.5 0.1 0.1\n1 0.2 0.2 0.1 0.1");
+    check_label_file(&mut state);
+    assert_eq!(state.annotation_bboxes.len(), 2);
+    assert!(state.label_conflict.is_none());
+
+    // With edits, they are kept until the user decides
+    state
+        .annotation_bboxes
+        .push(AnnoationBoundingBox::from_center(80., 80., 10., 10., 2));
+    write_labels("0 0.5 0.5 0.1 0.1\n1 0.2 0.2 0.1 0.1\n3 0.8 0.2 0.1 0.1");
+    state.labels_checked = None;
+    check_label_file(&mut state);
+    assert_eq!(state.annotation_bboxes.len(), 3);
+    assert_eq!(state.label_conflict.as_ref().map(|c| c.len()), Some(3));
+
+    // Saving waits for the decision, then merging keeps the boxes from both sides
+    assert!(!write_annotions_to_file(&mut state));
+    assert_eq!(boxes_on_disk(), 3);
+    merge_label_conflict(&mut state);
+    assert!(state.label_conflict.is_none());
+    assert_eq!(state.annotation_bboxes.len(), 4);
+    assert_eq!(boxes_on_disk(), 4);
+
+    // A change on disk found when saving also asks first
+    state.annotation_bboxes.pop();
+    write_labels("0 0.5 0.5 0.1 0.1");
+    assert!(!write_annotions_to_file(&mut state));
+    assert_eq!(state.label_conflict.as_ref().map(|c| c.len()), Some(1));
+    load_annotations_from_file(&mut state);
+    assert_eq!(state.annotation_bboxes.len(), 1);
+
+    // Saving without edits doesn't overwrite a newer file
+    write_labels("0 0.5 0.5 0.1 0.1\n1 0.2 0.2 0.1 0.1");
+    assert!(write_annotions_to_file(&mut state));
+    assert_eq!(boxes_on_disk(), 2);
+
+    // A failed write keeps the edits unsaved
+    state.current_path = Some(folder.join("missing").join("0.png"));
+    load_annotations_from_file(&mut state);
+    state.annotation_bboxes.push(a);
+    assert!(!write_annotions_to_file(&mut state));
+    assert!(has_unsaved_edits(&state));
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn search_images_in_background() {
+    use crate::appstate::OculanteState;
+    use crate::image_filter::ImageFilter;
+    use crate::lock::{lock_filename_for, LabelLock};
+    use crate::scrubber::Scrubber;
+    use crate::utils::{jump_to_image, update_image_search};
+    use std::time::Duration;
+
+    let folder = test_folder("search");
+    let images: Vec<PathBuf> = (0..3).map(|i| folder.join(format!("{i}.png"))).collect();
+    let theirs = LabelLock {
+        user: "someone".into(),
+        host: "elsewhere".into(),
+        ..Default::default()
+    };
+    std::fs::write(
+        lock_filename_for(&images[1]),
+        serde_json::to_string(&theirs).unwrap(),
+    )
+    .unwrap();
+
+    let mut state = OculanteState {
+        current_path: Some(images[0].clone()),
+        scrubber: Scrubber {
+            index: 0,
+            entries: images.clone(),
+            wrap: false,
+        },
+        image_dimension: (100, 100),
+        is_loaded: true,
+        ..Default::default()
+    };
+    jump_to_image(&mut state, ImageFilter::Unlocked, true);
+    // The lock files are read in the background
+    assert_eq!(state.current_path, Some(images[0].clone()));
+    for _ in 0..500 {
+        update_image_search(&mut state);
+        if state.image_search_job.is_none() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(state.scrubber.index, 2);
+    assert_eq!(state.current_path, Some(images[2].clone()));
+    _ = std::fs::remove_dir_all(&folder);
+}
+
+#[test]
+fn label_locks() {
+    use crate::image_filter::ImageFilter;
+    use crate::lock::{acquire_lock, lock_filename_for, locked_by_other, release_lock, LabelLock};
+
+    let folder = test_folder("lock");
+    let image = folder.join("0.png");
+    let lock_file = lock_filename_for(&image);
+    assert_eq!(lock_file, folder.join("0.lock"));
+
+    assert_eq!(acquire_lock(&image).unwrap(), None);
+    assert!(lock_file.exists());
+    assert!(locked_by_other(&image).is_none());
+    // Taking our own lock again renews it
+    assert_eq!(acquire_lock(&image).unwrap(), None);
+    release_lock(&image);
+    assert!(!lock_file.exists());
+
+    let mut theirs = LabelLock {
+        user: "someone".into(),
+        host: "elsewhere".into(),
+        ..Default::default()
+    };
+    std::fs::write(&lock_file, serde_json::to_string(&theirs).unwrap()).unwrap();
+    assert_eq!(acquire_lock(&image).unwrap(), Some(theirs.clone()));
+    assert_eq!(locked_by_other(&image), Some(theirs.clone()));
+    assert!(!ImageFilter::Unlocked.matches(&image, 1.));
+    // Other people's locks are never removed
+    release_lock(&image);
+    assert!(lock_file.exists());
+
+    // Another instance of the same user on the same computer holds its own lock
+    let other_session = LabelLock {
+        session: "another instance".into(),
+        ..Default::default()
+    };
+    assert!(LabelLock::new().is_mine());
+    assert!(!other_session.is_mine());
+    std::fs::write(&lock_file, serde_json::to_string(&other_session).unwrap()).unwrap();
+    assert_eq!(acquire_lock(&image).unwrap(), Some(other_session));
+    std::fs::write(&lock_file, serde_json::to_string(&theirs).unwrap()).unwrap();
+
+    // Until they expire
+    theirs.time = 0;
+    std::fs::write(&lock_file, serde_json::to_string(&theirs).unwrap()).unwrap();
+    assert!(theirs.is_stale());
+    assert!(ImageFilter::Unlocked.matches(&image, 1.));
+    assert_eq!(acquire_lock(&image).unwrap(), None);
+    assert!(locked_by_other(&image).is_none());
+    let ours: LabelLock =
+        serde_json::from_str(&std::fs::read_to_string(&lock_file).unwrap()).unwrap();
+    assert!(ours.is_mine());
+    // An unreadable lock is replaced as well
+    std::fs::write(&lock_file, "").unwrap();
+    assert_eq!(acquire_lock(&image).unwrap(), None);
+    assert!(locked_by_other(&image).is_none());
+    _ = std::fs::remove_dir_all(&folder);
+}
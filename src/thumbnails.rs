@@ -0,0 +1,253 @@
+use crate::image_loader::open_image;
+use crate::lint::find_tiny_boxes_in_image;
+use crate::tracks::read_labels_for_image;
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use log::debug;
+use notan::egui::{ColorImage, Context, TextureHandle, TextureOptions};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+/// Longest side of a thumbnail in pixels
+pub const THUMBNAIL_SIZE: u32 = 128;
+
+/// Textures to keep before the ones outside the visible tiles are dropped
+const MAX_TEXTURES: usize = 400;
+
+/// Work for the thumbnail thread
+enum Request {
+    Thumbnail(PathBuf),
+    /// Read the labels of an image, reporting boxes smaller than the minimum size. The
+    /// number tells the answer to this request from older ones.
+    Info(PathBuf, f32, u64),
+}
+
+/// What the thumbnail thread made
+enum Done {
+    Thumbnail(PathBuf, RgbaImage),
+    /// The image can't be read
+    NoThumbnail(PathBuf),
+    Info(PathBuf, u64, TileInfo),
+}
+
+/// Sends work to the thumbnail thread and receives its results
+type Worker = (Sender<Request>, Receiver<Done>);
+
+/// What a tile shows about the labels of its image
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TileInfo {
+    pub boxes: usize,
+    /// Number of boxes reported by the linter
+    pub tiny_boxes: usize,
+}
+
+impl TileInfo {
+    pub fn read(image_path: &Path, min_box_size: f32) -> Self {
+        Self {
+            boxes: read_labels_for_image(image_path).len(),
+            tiny_boxes: find_tiny_boxes_in_image(0, image_path, min_box_size).len(),
+        }
+    }
+}
+
+/// Thumbnails of the images in the folder, made in the background and cached on disk
+#[derive(Default)]
+pub struct Thumbnails {
+    textures: HashMap<PathBuf, TextureHandle>,
+    /// Images sent to the worker which have not come back yet
+    requested: HashSet<PathBuf>,
+    /// Images without a thumbnail, which are not requested again
+    failed: HashSet<PathBuf>,
+    /// Label summaries of the tiles, read when a tile is first shown
+    info: HashMap<PathBuf, TileInfo>,
+    /// Images whose label summary was sent to the worker and has not come back yet, with the
+    /// number of the request
+    info_requested: HashMap<PathBuf, u64>,
+    num_info_requests: u64,
+    worker: Option<Worker>,
+}
+
+impl fmt::Debug for Thumbnails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thumbnails")
+            .field("textures", &self.textures.len())
+            .field("requested", &self.requested.len())
+            .field("info", &self.info.len())
+            .finish()
+    }
+}
+
+impl Thumbnails {
+    fn request(&mut self, request: Request) -> bool {
+        let (sender, _) = self.worker.get_or_insert_with(spawn_worker);
+        sender.send(request).is_ok()
+    }
+
+    /// The thumbnail of an image, which is requested from the worker if it has not been made yet
+    pub fn get(&mut self, image_path: &Path) -> Option<&TextureHandle> {
+        if !self.textures.contains_key(image_path)
+            && !self.requested.contains(image_path)
+            && !self.failed.contains(image_path)
+            && self.request(Request::Thumbnail(image_path.to_path_buf()))
+        {
+            self.requested.insert(image_path.to_path_buf());
+        }
+        self.textures.get(image_path)
+    }
+
+    /// What the tile of an image shows about its labels, which is requested from the worker if
+    /// it has not been read yet
+    pub fn info(&mut self, image_path: &Path, min_box_size: f32) -> Option<TileInfo> {
+        if !self.info.contains_key(image_path) && !self.info_requested.contains_key(image_path) {
+            self.num_info_requests += 1;
+            let number = self.num_info_requests;
+            if self.request(Request::Info(
+                image_path.to_path_buf(),
+                min_box_size,
+                number,
+            )) {
+                self.info_requested.insert(image_path.to_path_buf(), number);
+            }
+        }
+        self.info.get(image_path).copied()
+    }
+
+    /// Whether the worker has not finished everything requested yet
+    pub fn is_busy(&self) -> bool {
+        !self.requested.is_empty() || !self.info_requested.is_empty()
+    }
+
+    /// Take in what the worker made. `visible` are kept if textures are dropped.
+    pub fn update(&mut self, ctx: &Context, visible: &[PathBuf]) {
+        if let Some((_, receiver)) = &self.worker {
+            for done in receiver.try_iter() {
+                match done {
+                    Done::Thumbnail(image_path, thumbnail) => {
+                        let image = ColorImage::from_rgba_unmultiplied(
+                            [thumbnail.width() as usize, thumbnail.height() as usize],
+                            thumbnail.as_raw(),
+                        );
+                        let texture = ctx.load_texture(
+                            image_path.to_string_lossy(),
+                            image,
+                            TextureOptions::LINEAR,
+                        );
+                        self.requested.remove(&image_path);
+                        self.textures.insert(image_path, texture);
+                    }
+                    Done::NoThumbnail(image_path) => {
+                        self.requested.remove(&image_path);
+                        self.failed.insert(image_path);
+                    }
+                    // Summaries of images invalidated in the meantime are requested again
+                    Done::Info(image_path, number, info) => {
+                        if self.info_requested.get(&image_path) == Some(&number) {
+                            self.info_requested.remove(&image_path);
+                            self.info.insert(image_path, info);
+                        }
+                    }
+                }
+            }
+        }
+        if self.textures.len() > MAX_TEXTURES {
+            self.textures.retain(|p, _| visible.contains(p));
+        }
+    }
+
+    /// Forget what is known about an image, after its labels or pixels have changed
+    pub fn invalidate(&mut self, image_path: &Path) {
+        self.info.remove(image_path);
+        self.info_requested.remove(image_path);
+    }
+
+    /// Forget what is known about the labels of all images
+    pub fn invalidate_all(&mut self) {
+        self.info.clear();
+        self.info_requested.clear();
+    }
+}
+
+/// Start the thread making thumbnails
+fn spawn_worker() -> Worker {
+    let (request_sender, request_receiver) = channel::<Request>();
+    let (result_sender, result_receiver) = channel();
+    std::thread::spawn(move || {
+        while let Ok(request) = request_receiver.recv() {
+            // The newest requests are for the tiles on screen, so they are made first
+            let mut pending = vec![request];
+            pending.extend(request_receiver.try_iter());
+            for request in pending.into_iter().rev() {
+                let done = match request {
+                    Request::Thumbnail(image_path) => match load_thumbnail(&image_path) {
+                        Ok(thumbnail) => Done::Thumbnail(image_path, thumbnail),
+                        Err(e) => {
+                            debug!("No thumbnail for {}: {e}", image_path.display());
+                            Done::NoThumbnail(image_path)
+                        }
+                    },
+                    Request::Info(image_path, min_box_size, number) => {
+                        let info = TileInfo::read(&image_path, min_box_size);
+                        Done::Info(image_path, number, info)
+                    }
+                };
+                if result_sender.send(done).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    (request_sender, result_receiver)
+}
+
+/// Where the thumbnail of an image is cached. Changing the image changes the file name.
+fn thumbnail_cache_path(image_path: &Path) -> Option<PathBuf> {
+    let modified = std::fs::metadata(image_path).ok()?.modified().ok()?;
+    let mut hasher = DefaultHasher::new();
+    image_path.hash(&mut hasher);
+    modified.hash(&mut hasher);
+    THUMBNAIL_SIZE.hash(&mut hasher);
+    Some(
+        dirs::cache_dir()?
+            .join("oculante")
+            .join("thumbnails")
+            .join(format!("{:016x}.png", hasher.finish())),
+    )
+}
+
+/// Read the cached thumbnail of an image, or make and cache it
+pub fn load_thumbnail(image_path: &Path) -> Result<RgbaImage> {
+    let cache_path = thumbnail_cache_path(image_path);
+    if let Some(cached) = cache_path.as_ref().and_then(|p| image::open(p).ok()) {
+        return Ok(cached.to_rgba8());
+    }
+
+    let frame = open_image(image_path)?
+        .recv()
+        .map_err(|_| anyhow!("Can't decode image"))?;
+    let (width, height) = frame.buffer.dimensions();
+    let scale = THUMBNAIL_SIZE as f32 / width.max(height).max(1) as f32;
+    let thumbnail = if scale < 1.0 {
+        image::imageops::thumbnail(
+            &frame.buffer,
+            ((width as f32 * scale) as u32).max(1),
+            ((height as f32 * scale) as u32).max(1),
+        )
+    } else {
+        frame.buffer
+    };
+
+    if let Some(cache_path) = cache_path {
+        if let Some(folder) = cache_path.parent() {
+            _ = std::fs::create_dir_all(folder);
+        }
+        if let Err(e) = thumbnail.save(&cache_path) {
+            debug!("Can't cache thumbnail {}: {e}", cache_path.display());
+        }
+    }
+    Ok(thumbnail)
+}
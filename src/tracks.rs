@@ -0,0 +1,510 @@
+use crate::appstate::{AnnoationBoundingBox, OculanteState};
+use crate::job::Job;
+use crate::lock::locked_by_other;
+use crate::utils::{
+    check_label_file, file_modified, file_names, labels_filename_for, write_annotions_to_file,
+};
+use crate::yolo_labels::{Label, Labels, Unnormaliser};
+use anyhow::{bail, Result};
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Minimum overlap for a box to continue a track of the previous image
+const TRACK_IOU_THRESHOLD: f32 = 0.3;
+
+/// A box marked as the first keyframe of an interpolation
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    /// The image the box belongs to
+    pub image_path: PathBuf,
+    /// The object id of the box, which finds it again even if the boxes of the image change
+    pub object_id: u32,
+}
+
+/// Read the labels belonging to an image. Missing and malformed label files are treated as
+/// empty.
+pub fn read_labels_for_image(image_path: &Path) -> Labels {
+    try_read_labels_for_image(image_path).unwrap_or_else(|e| {
+        debug!("Can't read the labels of {}: {e}", image_path.display());
+        Labels { labels: vec![] }
+    })
+}
+
+/// Read the labels belonging to an image before changing them. Missing label files are empty,
+/// but malformed ones are an error so they aren't overwritten.
+pub fn try_read_labels_for_image(image_path: &Path) -> std::io::Result<Labels> {
+    match Labels::from_file(labels_filename_for(image_path)) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Labels { labels: vec![] }),
+        result => result,
+    }
+}
+
+/// Find an object id that is not used in any label file of the sequence
+pub fn next_free_object_id(entries: &[PathBuf]) -> u32 {
+    entries
+        .iter()
+        .flat_map(|p| read_labels_for_image(p).labels)
+        .filter_map(|l| l.object_id)
+        .max()
+        .map(|id| id + 1)
+        .unwrap_or_default()
+}
+
+/// An object id that is neither used on disk nor in the current annotations
+pub fn unused_object_id(state: &OculanteState) -> u32 {
+    // Once the index has read all label files, it knows their ids
+    let free_on_disk = match &state.folder_index {
+        Some(index) if index.ready => index
+            .labels
+            .values()
+            .filter_map(|summary| summary.max_object_id)
+            .max()
+            .map(|id| id + 1)
+            .unwrap_or_default(),
+        _ => next_free_object_id(&state.scrubber.entries),
+    };
+    free_on_disk.max(
+        state
+            .annotation_bboxes
+            .iter()
+            .filter_map(|b| b.object_id.map(|id| id + 1))
+            .max()
+            .unwrap_or_default(),
+    )
+}
+
+/// The images an object appears in
+#[derive(Debug, Clone)]
+pub struct TrackSpan {
+    pub object_id: u32,
+    /// Index of the first image containing the object
+    pub first: usize,
+    /// Index of the last image containing the object
+    pub last: usize,
+    pub num_boxes: usize,
+}
+
+/// Collect all tracks of the sequence, ordered by object id
+pub fn collect_track_spans(entries: &[PathBuf]) -> Vec<TrackSpan> {
+    let mut spans: BTreeMap<u32, TrackSpan> = BTreeMap::new();
+    for (index, image_path) in entries.iter().enumerate() {
+        for object_id in read_labels_for_image(image_path)
+            .iter()
+            .filter_map(|l| l.object_id)
+        {
+            let span = spans.entry(object_id).or_insert(TrackSpan {
+                object_id,
+                first: index,
+                last: index,
+                num_boxes: 0,
+            });
+            span.last = index;
+            span.num_boxes += 1;
+        }
+    }
+    spans.into_values().collect()
+}
+
+/// Collect the tracks in the background once they are needed, and take in the result
+pub fn update_track_spans(state: &mut OculanteState) {
+    if let Some(spans) = state.track_spans_job.as_ref().and_then(|job| job.poll()) {
+        state.track_spans = Some(spans);
+        state.track_spans_job = None;
+    }
+    if state.track_spans.is_none() && state.track_spans_job.is_none() {
+        let entries = state.scrubber.entries.clone();
+        state.track_spans_job = Some(Job::spawn(move || collect_track_spans(&entries)));
+    }
+}
+
+/// Forget the tracks after label files were changed, so they are collected again
+pub fn invalidate_track_spans(state: &mut OculanteState) {
+    state.track_spans = None;
+    state.track_spans_job = None;
+}
+
+/// The label files a track operation changed, and the ones it had to leave alone
+#[derive(Debug, Clone, Default)]
+pub struct RewrittenLabels {
+    pub num_changed: usize,
+    /// Images locked by someone else, changed on disk meanwhile or with malformed labels
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Apply `f` to the labels of every image in the sequence. `f` returns whether the labels
+/// were changed, in which case they are written back. Images someone else is editing and
+/// label files changed by another program while they were updated are skipped.
+fn rewrite_labels<F>(entries: &[PathBuf], mut f: F) -> Result<RewrittenLabels>
+where
+    F: FnMut(usize, &mut Labels) -> bool,
+{
+    let mut rewritten = RewrittenLabels::default();
+    for (index, image_path) in entries.iter().enumerate() {
+        let labels_file = labels_filename_for(image_path);
+        let modified = file_modified(&labels_file);
+        let mut labels = match try_read_labels_for_image(image_path) {
+            Ok(labels) => labels,
+            Err(e) => {
+                debug!("Skipping {}: {e}", image_path.display());
+                rewritten.skipped.push(image_path.clone());
+                continue;
+            }
+        };
+        if !f(index, &mut labels) {
+            continue;
+        }
+        if locked_by_other(image_path).is_some() || file_modified(&labels_file) != modified {
+            debug!("Not changing {}", labels_file.display());
+            rewritten.skipped.push(image_path.clone());
+            continue;
+        }
+        labels.to_file(&labels_file)?;
+        rewritten.num_changed += 1;
+    }
+    Ok(rewritten)
+}
+
+/// Give all boxes of track `from` the id `to`. The tracks must not share an image.
+pub fn merge_tracks(entries: &[PathBuf], from: u32, to: u32) -> Result<RewrittenLabels> {
+    let num_overlapping = entries
+        .iter()
+        .map(|p| read_labels_for_image(p))
+        .filter(|labels| {
+            labels.iter().any(|l| l.object_id == Some(from))
+                && labels.iter().any(|l| l.object_id == Some(to))
+        })
+        .count();
+    if num_overlapping > 0 {
+        bail!("Objects {from} and {to} are both present in {num_overlapping} images");
+    }
+
+    rewrite_labels(entries, |_, labels| {
+        let mut changed = false;
+        for label in labels
+            .labels
+            .iter_mut()
+            .filter(|l| l.object_id == Some(from))
+        {
+            label.object_id = Some(to);
+            changed = true;
+        }
+        changed
+    })
+}
+
+/// Move all boxes of a track from image index `at` onwards to the new track `new_id`
+pub fn split_track(
+    entries: &[PathBuf],
+    object_id: u32,
+    at: usize,
+    new_id: u32,
+) -> Result<RewrittenLabels> {
+    rewrite_labels(entries, |index, labels| {
+        let mut changed = false;
+        if index >= at {
+            for label in labels
+                .labels
+                .iter_mut()
+                .filter(|l| l.object_id == Some(object_id))
+            {
+                label.object_id = Some(new_id);
+                changed = true;
+            }
+        }
+        changed
+    })
+}
+
+/// Number the tracks consecutively from zero, in order of their first appearance
+pub fn renumber_tracks(entries: &[PathBuf]) -> Result<RewrittenLabels> {
+    let mut new_ids: HashMap<u32, u32> = HashMap::new();
+    for image_path in entries {
+        for object_id in read_labels_for_image(image_path)
+            .iter()
+            .filter_map(|l| l.object_id)
+        {
+            let next_id = new_ids.len() as u32;
+            new_ids.entry(object_id).or_insert(next_id);
+        }
+    }
+
+    rewrite_labels(entries, |_, labels| {
+        let mut changed = false;
+        for label in labels.labels.iter_mut() {
+            if let Some(object_id) = label.object_id {
+                let new_id = new_ids[&object_id];
+                if new_id != object_id {
+                    label.object_id = Some(new_id);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    })
+}
+
+/// Run a track operation on the label files of the sequence in the background. The current
+/// annotations are saved before, so they take part in the operation.
+pub fn apply_track_operation<F>(state: &mut OculanteState, operation: F)
+where
+    F: FnOnce(&[PathBuf]) -> Result<RewrittenLabels> + Send + 'static,
+{
+    if state.current_path.is_none() || state.track_operation_job.is_some() {
+        return;
+    }
+    if !write_annotions_to_file(state) {
+        return;
+    }
+    let entries = state.scrubber.entries.clone();
+    state.track_operation_job = Some(Job::spawn(move || operation(&entries)));
+}
+
+/// Take in the result of a track operation once it is done
+pub fn update_track_operation(state: &mut OculanteState) {
+    let result = match state
+        .track_operation_job
+        .as_ref()
+        .and_then(|job| job.poll())
+    {
+        Some(result) => result,
+        None => return,
+    };
+    state.track_operation_job = None;
+    match result {
+        Ok(rewritten) => {
+            state.send_message(&format!("Updated {} label files", rewritten.num_changed));
+            if !rewritten.skipped.is_empty() {
+                state.send_message_warn(&format!(
+                    "Skipped {} images which are locked, malformed or changed meanwhile: {}",
+                    rewritten.skipped.len(),
+                    file_names(&rewritten.skipped)
+                ));
+            }
+        }
+        Err(e) => state.send_message_err(&format!("{e}")),
+    }
+    // The current labels are reloaded, or the user is asked if they were edited meanwhile
+    state.labels_checked = None;
+    check_label_file(state);
+    invalidate_track_spans(state);
+}
+
+/// Give every box of the current image without an object id the id of the best
+/// overlapping box of the same class in the previous image, or a new id if there is none.
+pub fn assign_track_ids(state: &mut OculanteState) {
+    let previous_bboxes = state
+        .scrubber
+        .index
+        .checked_sub(1)
+        .and_then(|i| state.scrubber.entries.get(i))
+        .map(|p| {
+            read_labels_for_image(p)
+                .unnormalise(state.image_dimension)
+                .iter()
+                .map(AnnoationBoundingBox::from_label)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    // All candidate pairs, best overlap first
+    let mut candidates = vec![];
+    for (id, bbox) in state.annotation_bboxes.iter().enumerate() {
+        if bbox.object_id.is_some() {
+            continue;
+        }
+        for previous in &previous_bboxes {
+            if let Some(object_id) = previous.object_id {
+                let iou = bbox.iou(*previous);
+                if previous.class_id == bbox.class_id && iou >= TRACK_IOU_THRESHOLD {
+                    candidates.push((iou, id, object_id));
+                }
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut num_matched = 0;
+    for (_, id, object_id) in candidates {
+        let already_used = state
+            .annotation_bboxes
+            .iter()
+            .any(|b| b.object_id == Some(object_id));
+        if state.annotation_bboxes[id].object_id.is_none() && !already_used {
+            state.annotation_bboxes[id].object_id = Some(object_id);
+            num_matched += 1;
+        }
+    }
+
+    // Only scan the sequence for a free id if we need one
+    let mut free_id = None;
+    let mut num_new = 0;
+    for id in 0..state.annotation_bboxes.len() {
+        if state.annotation_bboxes[id].object_id.is_none() {
+            let object_id = free_id.unwrap_or_else(|| unused_object_id(state));
+            state.annotation_bboxes[id].object_id = Some(object_id);
+            free_id = Some(object_id + 1);
+            num_new += 1;
+        }
+    }
+
+    if num_matched + num_new > 0 {
+        invalidate_track_spans(state);
+    }
+    debug!("Assigned track ids: {num_matched} matched, {num_new} new");
+}
+
+/// Remember the selected box as the start of an interpolation. A box without an object id
+/// is given a new one and saved.
+pub fn set_keyframe(state: &mut OculanteState) {
+    let (bbox_id, image_path) = match (state.selected_bbox_id, state.current_path.clone()) {
+        (Some(id), Some(p)) => (id, p),
+        _ => {
+            state.send_message_err("Select a box to use as keyframe");
+            return;
+        }
+    };
+    let object_id = match state.annotation_bboxes[bbox_id].object_id {
+        Some(object_id) => object_id,
+        None => {
+            let object_id = unused_object_id(state);
+            state.annotation_bboxes[bbox_id].object_id = Some(object_id);
+            write_annotions_to_file(state);
+            invalidate_track_spans(state);
+            object_id
+        }
+    };
+    state.keyframe = Some(Keyframe {
+        image_path,
+        object_id,
+    });
+    state.send_message("Keyframe set. Select the same object on another image to interpolate.");
+}
+
+/// Interpolate the box between the stored keyframe and the selected box, writing a box
+/// to the label file of every image in between. Both keyframes end up with the same object id.
+pub fn interpolate_from_keyframe(state: &mut OculanteState) {
+    let keyframe = match state.keyframe.clone() {
+        Some(k) => k,
+        None => {
+            state.send_message_err("No keyframe set");
+            return;
+        }
+    };
+    let (selected_bbox_id, current_path) =
+        match (state.selected_bbox_id, state.current_path.clone()) {
+            (Some(id), Some(p)) => (id, p),
+            _ => {
+                state.send_message_err("Select a box to interpolate to");
+                return;
+            }
+        };
+
+    let entries = &state.scrubber.entries;
+    let (start_index, end_index) = match (
+        entries.iter().position(|p| p == &keyframe.image_path),
+        entries.iter().position(|p| p == &current_path),
+    ) {
+        (Some(s), Some(e)) => (s, e),
+        _ => {
+            state.send_message_err("The keyframe is not part of the current image sequence");
+            return;
+        }
+    };
+    if start_index == end_index {
+        state.send_message_err("The keyframe is on the current image, select a box on another one");
+        return;
+    }
+
+    let object_id = keyframe.object_id;
+    let mut keyframe_labels = read_labels_for_image(&keyframe.image_path);
+    let keyframe_box = match keyframe_labels
+        .iter()
+        .position(|l| l.object_id == Some(object_id))
+    {
+        Some(i) => i,
+        None => {
+            state.send_message_err("The keyframe box does not exist anymore");
+            return;
+        }
+    };
+    let keyframe_label = keyframe_labels.labels[keyframe_box];
+    if state
+        .annotation_bboxes
+        .iter()
+        .enumerate()
+        .any(|(i, b)| i != selected_bbox_id && b.object_id == Some(object_id))
+    {
+        state.send_message_err(&format!("Object {object_id} already has another box here"));
+        return;
+    }
+
+    // Both ends become reviewed keyframes of the same track
+    keyframe_labels.labels[keyframe_box].interpolated = false;
+    if let Err(e) = keyframe_labels.to_file(labels_filename_for(&keyframe.image_path)) {
+        state.send_message_err(&format!("Could not update keyframe: {e}"));
+        return;
+    }
+    let selected_bbox = &mut state.annotation_bboxes[selected_bbox_id];
+    selected_bbox.object_id = Some(object_id);
+    selected_bbox.interpolated = false;
+
+    let current_label = selected_bbox.to_label(state.image_dimension.0, state.image_dimension.1);
+    let keyframe_label = Label {
+        interpolated: false,
+        ..keyframe_label
+    };
+
+    let (first, last, first_index, last_index) = if start_index < end_index {
+        (keyframe_label, current_label, start_index, end_index)
+    } else {
+        (current_label, keyframe_label, end_index, start_index)
+    };
+
+    let mut num_written = 0;
+    for index in first_index + 1..last_index {
+        let image_path = &entries[index];
+        let mut labels = match try_read_labels_for_image(image_path) {
+            Ok(labels) => labels,
+            Err(e) => {
+                debug!("Skipping {}: {e}", image_path.display());
+                continue;
+            }
+        };
+
+        // Boxes placed by hand always take precedence
+        if labels
+            .iter()
+            .any(|l| l.object_id == Some(object_id) && !l.interpolated)
+        {
+            debug!("Skipping keyframe in {}", image_path.display());
+            continue;
+        }
+
+        // Replace the result of a previous interpolation
+        labels
+            .labels
+            .retain(|l| !(l.object_id == Some(object_id) && l.interpolated));
+
+        let t = (index - first_index) as f32 / (last_index - first_index) as f32;
+        labels.labels.push(first.lerp(&last, t));
+
+        match labels.to_file(labels_filename_for(image_path)) {
+            Ok(_) => num_written += 1,
+            Err(e) => {
+                state.send_message_err(&format!("Could not write labels: {e}"));
+                return;
+            }
+        }
+    }
+
+    // Continue the track from here
+    state.keyframe = Some(Keyframe {
+        image_path: current_path,
+        object_id,
+    });
+    invalidate_track_spans(state);
+    state.send_message(&format!(
+        "Interpolated {num_written} boxes of object {object_id}"
+    ));
+}
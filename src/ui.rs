@@ -1,13 +1,32 @@
 #[cfg(feature = "file_open")]
 use crate::browse_for_image_path;
 use crate::{
-    appstate::OculanteState,
+    appstate::{AnnotationColumn, OculanteState, Tool},
+    batch::BatchAction,
+    dataset::{DatasetSource, SPLITS},
+    image_filter::ImageFilter,
+    job::Job,
+    lint::{find_tiny_boxes, is_tiny},
+    lock::LabelLock,
+    project::{ImageStatus, ProjectSettings, SortOrder},
+    query::{BOX_HELP, QUERY_HELP},
     set_zoom,
     settings::{set_system_theme, ColorTheme},
     shortcuts::{key_pressed, keypresses_as_string, lookup},
+    tracks::{
+        apply_track_operation, assign_track_ids, interpolate_from_keyframe, invalidate_track_spans,
+        merge_tracks, renumber_tracks, set_keyframe, split_track, unused_object_id,
+        update_track_spans,
+    },
     utils::{
-        clipboard_copy, disp_col, disp_col_norm, load_image_from_path, next_image, prev_image,
-        send_extended_info, set_title, toggle_fullscreen,
+        annotation_color, annotation_dimensions, annotation_tag, apply_query, clear_query,
+        clipboard_copy, close_dataset, delete_selected_annotation, disp_col, disp_col_norm,
+        duplicate_annotation, group_digits, jump_to_image, load_annotations_from_file,
+        load_image_from_path, merge_label_conflict, next_image, open_dataset, prev_image,
+        preview_batch, remove_annotation, reorder_annotation, revert_last_batch, review_step,
+        run_batch, send_extended_info, set_image_status, set_sort_order, set_title,
+        snap_annotation, stamp_size, toggle_fullscreen, write_annotions_to_file,
+        zoom_to_annotation, zoom_to_selected_annotation,
     },
 };
 
@@ -18,9 +37,10 @@ use egui_phosphor::regular::*;
 use arboard::Clipboard;
 use notan::{
     egui::{self, *},
+    graphics::color::Color,
     prelude::{App, Graphics},
 };
-use std::{collections::BTreeSet, ops::RangeInclusive};
+use std::{collections::BTreeSet, ops::RangeInclusive, path::PathBuf};
 const PANEL_WIDTH: f32 = 240.0;
 
 #[cfg(feature = "turbo")]
@@ -234,69 +254,1287 @@ pub fn info_ui(ctx: &Context, state: &mut OculanteState, _gfx: &mut Graphics) {
                                 ui.end_row();
                             }
 
-                            ui.label_i(&format!("{PALETTE} RGBA"));
-                            ui.label(
-                                RichText::new(disp_col(state.sampled_color))
-                                    .monospace()
-                                    .background_color(Color32::from_rgba_unmultiplied(
-                                        255, 255, 255, 6,
-                                    )),
-                            );
-                            ui.end_row();
+                            ui.label_i(&format!("{PALETTE} RGBA"));
+                            ui.label(
+                                RichText::new(disp_col(state.sampled_color))
+                                    .monospace()
+                                    .background_color(Color32::from_rgba_unmultiplied(
+                                        255, 255, 255, 6,
+                                    )),
+                            );
+                            ui.end_row();
+
+                            ui.label_i(&format!("{PALETTE} RGBA"));
+                            ui.label(
+                                RichText::new(disp_col_norm(state.sampled_color, 255.))
+                                    .monospace()
+                                    .background_color(Color32::from_rgba_unmultiplied(
+                                        255, 255, 255, 6,
+                                    )),
+                            );
+                            ui.end_row();
+
+                            ui.label_i("⊞ Pos");
+                            ui.label(
+                                RichText::new(format!(
+                                    "{:.0},{:.0}",
+                                    state.cursor_relative.x, state.cursor_relative.y
+                                ))
+                                .monospace()
+                                .background_color(
+                                    Color32::from_rgba_unmultiplied(255, 255, 255, 6),
+                                ),
+                            );
+                            ui.end_row();
+
+                            ui.label_i(" UV");
+                            ui.label(
+                                RichText::new(format!(
+                                    "{:.3},{:.3}",
+                                    uv_center.0,
+                                    1.0 - uv_center.1
+                                ))
+                                .monospace()
+                                .background_color(
+                                    Color32::from_rgba_unmultiplied(255, 255, 255, 6),
+                                ),
+                            );
+                            ui.end_row();
+                        });
+                    }
+                });
+
+            ui.collapsing("Tracks", |ui| {
+                tracks_ui(ui, state);
+            });
+
+            ui.collapsing("Stamp sizes", |ui| {
+                stamp_sizes_ui(ui, state);
+            });
+
+            ui.collapsing("Classes", |ui| {
+                classes_ui(ui, state);
+            });
+
+            ui.collapsing("Lint", |ui| {
+                lint_ui(ui, state);
+            });
+
+            ui.collapsing("Query", |ui| {
+                query_ui(ui, state);
+            });
+
+            ui.collapsing("Batch", |ui| {
+                batch_ui(ui, state);
+            });
+
+            egui::CollapsingHeader::new("Annotations")
+                .default_open(true)
+                .show(ui, |ui| {
+                    annotations_ui(ui, state);
+                });
+        });
+}
+
+fn to_color32(c: Color) -> Color32 {
+    let [r, g, b, a] = c.rgba_u8();
+    Color32::from_rgba_unmultiplied(r, g, b, a)
+}
+
+/// A grid of thumbnails of the images in the scrub bar
+pub fn thumbnail_grid(ctx: &Context, state: &mut OculanteState) {
+    egui::SidePanel::right("thumbnails")
+        .default_width(3. * (state.persistent_settings.thumbnail_size + 8.))
+        .show(ctx, |ui| {
+            thumbnail_grid_ui(ui, state);
+        });
+}
+
+fn thumbnail_grid_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let len = state.scrubber.entries.len();
+
+    ui.horizontal(|ui| {
+        ui.label(format!("{len} images"));
+        ui.add(
+            egui::Slider::new(&mut state.persistent_settings.thumbnail_size, 48.0..=256.0)
+                .show_value(false),
+        )
+        .on_hover_text("Tile size");
+    });
+
+    let selection: Vec<PathBuf> = state.thumbnail_selection.iter().cloned().collect();
+    ui.horizontal_wrapped(|ui| {
+        ui.label(format!("{} selected:", selection.len()));
+        for status in [
+            ImageStatus::Done,
+            ImageStatus::Background,
+            ImageStatus::Unvisited,
+        ]
+        .iter()
+        .cloned()
+        {
+            if ui
+                .add_enabled(
+                    !selection.is_empty() && state.project_folder.is_some(),
+                    egui::Button::new(status.name()),
+                )
+                .on_hover_text(
+                    "Set the status of the selected images. Ctrl or shift click to select more.",
+                )
+                .clicked()
+            {
+                set_image_status(state, &selection, status);
+            }
+        }
+    });
+    ui.separator();
+
+    let tile_size = state.persistent_settings.thumbnail_size;
+    let spacing = ui.spacing().item_spacing;
+    let columns = ((ui.available_width() + spacing.x) / (tile_size + spacing.x))
+        .floor()
+        .max(1.) as usize;
+    let rows = (len + columns - 1) / columns;
+    let mut visible = vec![];
+    let mut clicked = None;
+    // Only the rows on screen are laid out, so large folders stay fast
+    egui::ScrollArea::vertical()
+        .auto_shrink([false; 2])
+        .show_rows(ui, tile_size, rows, |ui, row_range| {
+            for row in row_range {
+                ui.horizontal(|ui| {
+                    for index in row * columns..((row + 1) * columns).min(len) {
+                        if thumbnail_tile(ui, state, index, tile_size).clicked() {
+                            clicked = Some(index);
+                        }
+                        visible.push(state.scrubber.entries[index].clone());
+                    }
+                });
+            }
+        });
+    state.thumbnails.update(ui.ctx(), &visible);
+    if state.thumbnails.is_busy() {
+        ui.ctx().request_repaint();
+    }
+
+    if let Some(index) = clicked {
+        let modifiers = ui.input(|i| i.modifiers);
+        let image_path = state.scrubber.entries[index].clone();
+        if modifiers.command {
+            if !state.thumbnail_selection.remove(&image_path) {
+                state.thumbnail_selection.insert(image_path.clone());
+            }
+            state.thumbnail_anchor = Some(image_path);
+        } else if modifiers.shift {
+            let anchor = state
+                .thumbnail_anchor
+                .as_ref()
+                .and_then(|anchor| state.scrubber.entries.iter().position(|p| p == anchor))
+                .unwrap_or(state.scrubber.index);
+            let range = &state.scrubber.entries[anchor.min(index)..=anchor.max(index)];
+            state.thumbnail_selection.extend(range.iter().cloned());
+        } else {
+            state.thumbnail_selection = std::iter::once(image_path.clone()).collect();
+            state.thumbnail_anchor = Some(image_path);
+            if write_annotions_to_file(state) {
+                let p = state.scrubber.set(index);
+                load_image_from_path(&p, state);
+            }
+        }
+    }
+}
+
+/// One tile of the thumbnail grid, with badges for the boxes, status and lint warnings
+fn thumbnail_tile(ui: &mut Ui, state: &mut OculanteState, index: usize, size: f32) -> Response {
+    let image_path = state.scrubber.entries[index].clone();
+    let (rect, response) = ui.allocate_exact_size(Vec2::splat(size), Sense::click());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2., ui.visuals().extreme_bg_color);
+
+    if let Some(texture) = state.thumbnails.get(&image_path) {
+        let image_size = texture.size_vec2();
+        let scale = (size / image_size.x).min(size / image_size.y);
+        painter.image(
+            texture.id(),
+            Rect::from_center_size(rect.center(), image_size * scale),
+            Rect::from_min_max(pos2(0., 0.), pos2(1., 1.)),
+            Color32::WHITE,
+        );
+    }
+
+    let min_box_size = state.persistent_settings.min_box_size;
+    let info = state.thumbnails.info(&image_path, min_box_size);
+    let status = state
+        .project_folder
+        .as_deref()
+        .map(|folder| state.project.image_status(folder, &image_path))
+        .unwrap_or_default();
+
+    let badge = |pos: Pos2, align: Align2, text: String, fill: Color32| {
+        let galley = painter.layout_no_wrap(text, FontId::proportional(11.), Color32::WHITE);
+        let badge_rect = align.anchor_rect(Rect::from_min_size(pos, galley.size() + vec2(6., 2.)));
+        painter.rect_filled(badge_rect, 2., fill);
+        painter.galley(badge_rect.min + vec2(3., 1.), galley);
+    };
+    let inner = rect.shrink(2.);
+    if let Some(info) = info {
+        badge(
+            inner.left_top(),
+            Align2::LEFT_TOP,
+            format!("{RECTANGLE} {}", info.boxes),
+            Color32::from_black_alpha(180),
+        );
+    }
+    match status {
+        ImageStatus::Done => badge(
+            inner.right_top(),
+            Align2::RIGHT_TOP,
+            CHECK.into(),
+            Color32::from_rgb(40, 150, 60),
+        ),
+        ImageStatus::Background => badge(
+            inner.right_top(),
+            Align2::RIGHT_TOP,
+            IMAGE_SQUARE.into(),
+            Color32::from_rgb(60, 90, 170),
+        ),
+        ImageStatus::Unvisited => badge(
+            inner.right_top(),
+            Align2::RIGHT_TOP,
+            CIRCLE_DASHED.into(),
+            Color32::from_black_alpha(180),
+        ),
+        ImageStatus::Visited => {}
+    }
+    if let Some(info) = info.filter(|info| info.tiny_boxes > 0) {
+        badge(
+            inner.left_bottom(),
+            Align2::LEFT_BOTTOM,
+            format!("{WARNING} {}", info.tiny_boxes),
+            Color32::from_rgb(180, 120, 0),
+        );
+    }
+
+    let stroke = if state.thumbnail_selection.contains(&image_path) {
+        Some(Stroke::new(3., ui.visuals().selection.bg_fill))
+    } else if index == state.scrubber.index {
+        Some(Stroke::new(2., ui.visuals().strong_text_color()))
+    } else {
+        None
+    };
+    if let Some(stroke) = stroke {
+        painter.rect_stroke(rect.shrink(1.), 2., stroke);
+    }
+
+    let name = image_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    response.on_hover_text(format!("{name}\n{}", status.name()))
+}
+
+/// Tell the user that someone else is editing the labels of the image
+pub fn lock_banner_ui(ui: &mut Ui, lock: &LabelLock) {
+    ui.horizontal(|ui| {
+        ui.colored_label(
+            Color32::GOLD,
+            format!(
+                "{LOCK} {} on {} is editing this image, so its labels are read-only.",
+                lock.user, lock.host
+            ),
+        );
+        ui.label(format!("Locked {} min ago", lock.age().as_secs() / 60));
+    });
+}
+
+/// Ask what to do with labels another program wrote while the current image had unsaved edits
+pub fn label_conflict_ui(ctx: &Context, state: &mut OculanteState) {
+    let theirs = match &state.label_conflict {
+        Some(theirs) => theirs.clone(),
+        None => return,
+    };
+    let name = state
+        .current_path
+        .as_ref()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    egui::Window::new("Labels changed on disk")
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "Another program changed the labels of {name} while you were editing them."
+            ));
+            ui.label(format!(
+                "{} boxes here, {} on disk.",
+                state.annotation_bboxes.len(),
+                theirs.len()
+            ));
+            ui.horizontal(|ui| {
+                if ui
+                    .button("Keep mine")
+                    .on_hover_text("Overwrite the file with the boxes here")
+                    .clicked()
+                {
+                    state.label_conflict = None;
+                    write_annotions_to_file(state);
+                }
+                if ui
+                    .button("Use theirs")
+                    .on_hover_text("Discard the edits made here")
+                    .clicked()
+                {
+                    load_annotations_from_file(state);
+                }
+                if ui
+                    .button("Merge")
+                    .on_hover_text("Keep the changes made here and the ones made on disk")
+                    .clicked()
+                {
+                    merge_label_conflict(state);
+                }
+            });
+        });
+}
+
+/// Draw a tag with the class name above the top left corner of every box. The tags have
+/// a fixed size on screen and sit below the panels.
+pub fn annotation_tags(ctx: &Context, state: &OculanteState) {
+    let painter = ctx.layer_painter(LayerId::new(Order::Background, Id::new("annotation_tags")));
+    let geometry = &state.image_geometry;
+    let min_size = state.persistent_settings.annotation_tag_min_size;
+
+    for bbox in state
+        .annotation_bboxes
+        .iter()
+        .filter(|b| state.annotation_visibility.is_visible(b))
+    {
+        let (width, height) = bbox.size();
+        if width * geometry.scale < min_size || height * geometry.scale < min_size {
+            continue;
+        }
+
+        let color = annotation_color(state, bbox);
+        // Dark text on bright colours
+        let luma = 0.299 * color.r + 0.587 * color.g + 0.114 * color.b;
+        let text_color = if luma > 0.5 {
+            Color32::BLACK
+        } else {
+            Color32::WHITE
+        };
+        let galley = painter.layout_no_wrap(
+            annotation_tag(state, bbox),
+            FontId::proportional(12.),
+            text_color,
+        );
+
+        let corner = pos2(
+            geometry.offset.x + bbox.x_min() * geometry.scale,
+            geometry.offset.y + bbox.y_min() * geometry.scale,
+        );
+        let size = galley.size() + vec2(6., 2.);
+        // Put the tag inside the box if there is no room above it
+        let min = if corner.y - size.y < 0. {
+            corner
+        } else {
+            corner - vec2(0., size.y)
+        };
+        let mut opacity = color.a;
+        if bbox.interpolated {
+            opacity *= 0.5;
+        }
+        let fill = to_color32(Color { a: 1.0, ..color }).gamma_multiply(opacity);
+        painter.rect_filled(Rect::from_min_size(min, size), 2., fill);
+        painter.galley_with_color(
+            min + vec2(3., 1.),
+            galley,
+            text_color.gamma_multiply(opacity),
+        );
+    }
+}
+
+/// The menu opened by right clicking a box
+pub fn annotation_context_menu(ctx: &Context, state: &mut OculanteState) {
+    let menu = match state.annotation_context_menu {
+        Some(menu) if menu.bbox_id < state.annotation_bboxes.len() => menu,
+        _ => {
+            state.annotation_context_menu = None;
+            return;
+        }
+    };
+    let id = menu.bbox_id;
+    let mut done = false;
+
+    let response = egui::Area::new("annotation_context_menu")
+        .order(Order::Foreground)
+        .fixed_pos(pos2(menu.position.x, menu.position.y))
+        .show(ctx, |ui| {
+            egui::Frame::menu(ui.style()).show(ui, |ui| {
+                ui.set_min_width(140.);
+                let editable = state.locked_by.is_none()
+                    && state
+                        .annotation_visibility
+                        .is_editable(&state.annotation_bboxes[id]);
+                ui.add_enabled_ui(editable, |ui| {
+                    ui.menu_button("Class", |ui| {
+                        for class_id in 0..state.label_colors.len() as u32 {
+                            let bbox = &mut state.annotation_bboxes[id];
+                            if ui
+                                .selectable_label(
+                                    bbox.class_id == class_id,
+                                    state.project.class_name(class_id),
+                                )
+                                .clicked()
+                            {
+                                bbox.class_id = class_id;
+                                bbox.interpolated = false;
+                                done = true;
+                            }
+                        }
+                    });
+                    if ui.button(format!("{COPY} Duplicate")).clicked() {
+                        duplicate_annotation(state, id);
+                        done = true;
+                    }
+                    if ui.button(format!("{TRASH} Delete")).clicked() {
+                        remove_annotation(state, id);
+                        state.send_message("Deleted annotation");
+                        done = true;
+                    }
+                    ui.separator();
+                    if ui
+                        .button(format!("{ARROW_LINE_UP} Bring to front"))
+                        .clicked()
+                    {
+                        reorder_annotation(state, id, true);
+                        done = true;
+                    }
+                    if ui
+                        .button(format!("{ARROW_LINE_DOWN} Send to back"))
+                        .clicked()
+                    {
+                        reorder_annotation(state, id, false);
+                        done = true;
+                    }
+                });
+                let class_id = state.annotation_bboxes[id].class_id;
+                let locked = state.annotation_visibility.class(class_id).locked;
+                if ui
+                    .button(if locked {
+                        format!("{LOCK_OPEN} Unlock class")
+                    } else {
+                        format!("{LOCK} Lock class")
+                    })
+                    .clicked()
+                {
+                    state.annotation_visibility.class_mut(class_id).locked = !locked;
+                    done = true;
+                }
+                ui.separator();
+                if ui.button(format!("{CLIPBOARD} Copy coordinates")).clicked() {
+                    let bbox = state.annotation_bboxes[id];
+                    let text = format!(
+                        "{:.0},{:.0},{:.0},{:.0}",
+                        bbox.x_min(),
+                        bbox.y_min(),
+                        bbox.x_max(),
+                        bbox.y_max()
+                    );
+                    if let Ok(clipboard) = &mut Clipboard::new() {
+                        _ = clipboard.set_text(text);
+                    }
+                    done = true;
+                }
+                if ui
+                    .button(format!("{MAGNIFYING_GLASS_PLUS} Zoom to box"))
+                    .clicked()
+                {
+                    zoom_to_annotation(state, id);
+                    done = true;
+                }
+            });
+        });
+
+    if done || response.response.clicked_elsewhere() {
+        state.annotation_context_menu = None;
+    }
+}
+
+/// Restrict the image sequence to the images whose labels match a query
+fn query_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let response = ui
+        .add(
+            egui::TextEdit::singleline(&mut state.query_text)
+                .hint_text("count(class == 2) > 3 && min_area < 100")
+                .desired_width(f32::INFINITY),
+        )
+        .on_hover_text(QUERY_HELP);
+    let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+
+    ui.horizontal(|ui| {
+        if ui.button(format!("{FUNNEL} Apply")).clicked() || submitted {
+            apply_query(state);
+        }
+        if state.query_job.is_some() {
+            ui.spinner();
+        }
+        if ui
+            .add_enabled(
+                state.dataset_query.is_some(),
+                egui::Button::new(format!("{X} Clear")),
+            )
+            .clicked()
+        {
+            clear_query(state);
+        }
+    });
+
+    match &state.dataset_query {
+        Some(query) => ui.label(format!(
+            "{} images match '{}'",
+            query.matches.len(),
+            query.expression
+        )),
+        None => ui.label("Showing all images"),
+    };
+}
+
+fn batch_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let mut changed = ui
+        .add(
+            egui::TextEdit::singleline(&mut state.batch_expression)
+                .hint_text("width * height < 64")
+                .desired_width(f32::INFINITY),
+        )
+        .on_hover_text(format!("Boxes to change. Variables: {BOX_HELP}"))
+        .changed();
+
+    let num_classes = state.label_colors.len() as u32;
+    let project = &state.project;
+    let action = &mut state.batch_action;
+    ui.horizontal(|ui| {
+        let actions = [
+            BatchAction::Delete,
+            BatchAction::SetClass(0),
+            BatchAction::Expand(5.0),
+        ];
+        egui::ComboBox::from_id_source("batch action")
+            .selected_text(action.name())
+            .show_ui(ui, |ui| {
+                for a in actions.iter() {
+                    if ui
+                        .selectable_label(action.name() == a.name(), a.name())
+                        .clicked()
+                        && action.name() != a.name()
+                    {
+                        *action = *a;
+                        changed = true;
+                    }
+                }
+            });
+
+        match action {
+            BatchAction::Delete => {}
+            BatchAction::SetClass(class_id) => {
+                egui::ComboBox::from_id_source("batch class")
+                    .selected_text(project.class_name(*class_id))
+                    .show_ui(ui, |ui| {
+                        for id in 0..num_classes {
+                            changed |= ui
+                                .selectable_value(class_id, id, project.class_name(id))
+                                .changed();
+                        }
+                    });
+            }
+            BatchAction::Expand(percent) => {
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(percent)
+                            .speed(0.5)
+                            .clamp_range(-90.0..=500.0)
+                            .suffix(" %"),
+                    )
+                    .changed();
+            }
+        }
+    });
+
+    // A preview only stays valid for what it was made from
+    if changed {
+        state.batch_plan = None;
+    }
+
+    ui.horizontal(|ui| {
+        if ui
+            .button(format!("{EYE} Preview"))
+            .on_hover_text("Show which boxes would change in the images of the scrub bar")
+            .clicked()
+        {
+            preview_batch(state);
+        }
+        let ready = state
+            .batch_plan
+            .as_ref()
+            .map(|plan| !plan.changes.is_empty())
+            .unwrap_or_default();
+        if ui
+            .add_enabled(ready, egui::Button::new(format!("{CHECK} Apply")))
+            .on_hover_text("Change all label files. A backup is kept to revert.")
+            .clicked()
+        {
+            run_batch(state);
+        }
+    });
+
+    if let Some(plan) = &state.batch_plan {
+        ui.label(format!(
+            "{}: {} boxes in {} images",
+            plan.action.name(),
+            plan.num_boxes(),
+            plan.changes.len()
+        ));
+        let mut load = None;
+        egui::ScrollArea::vertical()
+            .id_source("batch preview")
+            .max_height(120.0)
+            .show(ui, |ui| {
+                for change in &plan.changes {
+                    let name = change
+                        .image_path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    if ui
+                        .selectable_label(
+                            state.current_path.as_ref() == Some(&change.image_path),
+                            format!("{name}: {}", change.affected),
+                        )
+                        .clicked()
+                    {
+                        load = Some(change.image_path.clone());
+                    }
+                }
+            });
+        if let Some(p) = load {
+            if write_annotions_to_file(state) {
+                load_image_from_path(&p, state);
+            }
+        }
+    }
+
+    if state.batch_backup.is_some() {
+        ui.separator();
+        if ui
+            .button(format!("{ARROW_COUNTER_CLOCKWISE} Revert last batch"))
+            .clicked()
+        {
+            revert_last_batch(state);
+        }
+    }
+}
+
+/// Report boxes smaller than the minimum size
+fn lint_ui(ui: &mut Ui, state: &mut OculanteState) {
+    ui.horizontal(|ui| {
+        ui.label("Minimum size");
+        ui.add(
+            egui::DragValue::new(&mut state.persistent_settings.min_box_size)
+                .speed(0.1)
+                .clamp_range(0.0..=1000.0)
+                .suffix(" px"),
+        )
+        .on_hover_text("Boxes narrower or lower than this many image pixels are reported");
+    });
+
+    let min_size = state.persistent_settings.min_box_size;
+    let tiny_ids: Vec<usize> = (0..state.annotation_bboxes.len())
+        .filter(|id| {
+            let (width, height) = state.annotation_bboxes[*id].size();
+            is_tiny(width, height, min_size)
+        })
+        .collect();
+
+    if tiny_ids.is_empty() {
+        ui.label("No tiny boxes in this image");
+    } else {
+        for id in &tiny_ids {
+            let (width, height) = state.annotation_bboxes[*id].size();
+            if ui
+                .selectable_label(
+                    state.selected_bbox_id == Some(*id),
+                    format!("Box {id}: {width:.1} x {height:.1} px"),
+                )
+                .clicked()
+            {
+                state.selected_bbox_id = Some(*id);
+                zoom_to_annotation(state, *id);
+            }
+        }
+        // Boxes of locked or hidden classes stay
+        let removable: Vec<usize> = tiny_ids
+            .iter()
+            .copied()
+            .filter(|id| {
+                state.locked_by.is_none()
+                    && state
+                        .annotation_visibility
+                        .is_editable(&state.annotation_bboxes[*id])
+            })
+            .collect();
+        if ui
+            .add_enabled(
+                !removable.is_empty(),
+                egui::Button::new(format!("{TRASH} Remove tiny boxes")),
+            )
+            .clicked()
+        {
+            for id in removable.iter().rev() {
+                remove_annotation(state, *id);
+            }
+            state.send_message(&format!("Removed {} tiny boxes", removable.len()));
+        }
+    }
+
+    ui.separator();
+    if ui
+        .button("Check all images")
+        .on_hover_text("Look for tiny boxes in the label files of all images in this folder")
+        .clicked()
+        && state.current_path.is_some()
+    {
+        write_annotions_to_file(state);
+        let entries = state.scrubber.entries.clone();
+        state.tiny_boxes_job = Some(Job::spawn(move || find_tiny_boxes(&entries, min_size)));
+    }
+
+    if let Some(tiny_boxes) = state.tiny_boxes_job.as_ref().and_then(|job| job.poll()) {
+        state.tiny_boxes = Some(tiny_boxes);
+        state.tiny_boxes_job = None;
+    }
+    if state.tiny_boxes_job.is_some() {
+        ui.spinner();
+        ui.ctx().request_repaint();
+    }
+
+    if let Some(tiny_boxes) = state.tiny_boxes.clone() {
+        ui.label(format!("{} tiny boxes in this folder", tiny_boxes.len()));
+        egui::Grid::new("tiny boxes").striped(true).show(ui, |ui| {
+            for tiny_box in &tiny_boxes {
+                if ui
+                    .selectable_label(
+                        tiny_box.image_index == state.scrubber.index,
+                        format!("Image {}", tiny_box.image_index + 1),
+                    )
+                    .on_hover_text("Go to this image")
+                    .clicked()
+                    && write_annotions_to_file(state)
+                {
+                    let p = state.scrubber.set(tiny_box.image_index);
+                    load_image_from_path(&p, state);
+                }
+                ui.label(format!("Box {}", tiny_box.bbox_id));
+                ui.label(format!("{:.1} x {:.1} px", tiny_box.width, tiny_box.height));
+                ui.end_row();
+            }
+        });
+    }
+}
+
+/// Legend of the classes with their visibility, opacity and lock
+fn classes_ui(ui: &mut Ui, state: &mut OculanteState) {
+    ui.horizontal(|ui| {
+        ui.label("Opacity");
+        ui.add(egui::Slider::new(
+            &mut state.persistent_settings.annotation_opacity,
+            0.0..=1.0,
+        ));
+    });
+    tooltip(
+        ui.checkbox(&mut state.annotation_visibility.hide_all, "Hide all"),
+        "Hide all boxes to look at the image",
+        &lookup(
+            &state.persistent_settings.shortcuts,
+            &crate::shortcuts::InputEvent::HideAnnotations,
+        ),
+        ui,
+    );
+
+    egui::Grid::new("classes").striped(true).show(ui, |ui| {
+        for class_id in 0..state.label_colors.len() as u32 {
+            let color = to_color32(state.label_colors[class_id as usize]);
+            ui.horizontal(|ui| {
+                ui.label(RichText::new("■").color(color));
+                ui.label(state.project.class_name(class_id));
+            });
+
+            let display = state.annotation_visibility.class_mut(class_id);
+            if ui
+                .button(if display.hidden { EYE_SLASH } else { EYE })
+                .on_hover_text("Show or hide this class")
+                .clicked()
+            {
+                display.hidden = !display.hidden;
+            }
+            if ui
+                .button(if display.locked { LOCK } else { LOCK_OPEN })
+                .on_hover_text("Lock this class against edits")
+                .clicked()
+            {
+                display.locked = !display.locked;
+            }
+            ui.add(egui::Slider::new(&mut display.opacity, 0.0..=1.0).show_value(false));
+            ui.end_row();
+        }
+    });
+}
+
+/// Position and size of the box being drawn or dragged, next to the cursor
+pub fn annotation_readout(ctx: &Context, state: &OculanteState) {
+    let bbox = match state
+        .bbox_edit_mode
+        .bbox_id()
+        .and_then(|id| state.annotation_bboxes.get(id))
+    {
+        Some(bbox) => bbox,
+        None => return,
+    };
+    let painter = ctx.layer_painter(LayerId::new(
+        Order::Foreground,
+        Id::new("annotation_readout"),
+    ));
+    let galley = painter.layout_no_wrap(
+        annotation_dimensions(state, bbox),
+        FontId::monospace(11.),
+        Color32::WHITE,
+    );
+    // Above the cursor, so it does not cover the loupe
+    let min = pos2(state.cursor.x + 16., state.cursor.y - 16. - galley.size().y);
+    let rect = Rect::from_min_size(min, galley.size()).expand(3.);
+    painter.rect_filled(rect, 2., Color32::from_black_alpha(180));
+    painter.galley(min, galley);
+}
+
+/// Exact position and size of the selected box
+fn selected_annotation_ui(ui: &mut Ui, state: &mut OculanteState) {
+    ui.horizontal(|ui| {
+        ui.checkbox(
+            &mut state.persistent_settings.snap_to_pixels,
+            "Snap to pixels",
+        )
+        .on_hover_text("Keep the corners of boxes on whole pixels. Hold ctrl to disable.");
+        ui.checkbox(
+            &mut state.persistent_settings.snap_to_edges,
+            "Snap to edges",
+        )
+        .on_hover_text(
+            "Snap to the image border and the edges of other boxes. Hold ctrl to disable.",
+        );
+    });
+
+    let id = match state.selected_bbox_id {
+        Some(id) if id < state.annotation_bboxes.len() => id,
+        _ => return,
+    };
+    let (image_width, image_height) = (
+        state.image_dimension.0 as f32,
+        state.image_dimension.1 as f32,
+    );
+    let bbox = state.annotation_bboxes[id];
+    let (mut x, mut y) = (bbox.x_min(), bbox.y_min());
+    let (mut width, mut height) = bbox.size();
+    let mut changed = false;
+
+    let editable = state.locked_by.is_none() && state.annotation_visibility.is_editable(&bbox);
+    ui.add_enabled_ui(editable, |ui| {
+        egui::Grid::new("selected annotation").show(ui, |ui| {
+            ui.label("Pixels");
+            for value in [&mut x, &mut y, &mut width, &mut height] {
+                changed |= ui
+                    .add(egui::DragValue::new(value).speed(1.0).max_decimals(1))
+                    .changed();
+            }
+            ui.end_row();
+
+            // YOLO uses the centre of the box
+            let mut x_centre = (x + width / 2.) / image_width;
+            let mut y_centre = (y + height / 2.) / image_height;
+            let mut normalised_width = width / image_width;
+            let mut normalised_height = height / image_height;
+            let mut normalised_changed = false;
+            ui.label("Normalised");
+            for value in [
+                &mut x_centre,
+                &mut y_centre,
+                &mut normalised_width,
+                &mut normalised_height,
+            ] {
+                normalised_changed |= ui
+                    .add(
+                        egui::DragValue::new(value)
+                            .speed(0.001)
+                            .max_decimals(4)
+                            .clamp_range(0.0..=1.0),
+                    )
+                    .changed();
+            }
+            ui.end_row();
+
+            if normalised_changed {
+                width = normalised_width * image_width;
+                height = normalised_height * image_height;
+                x = x_centre * image_width - width / 2.;
+                y = y_centre * image_height - height / 2.;
+                changed = true;
+            }
+        });
+    });
+
+    if changed && width > 0. && height > 0. {
+        let bbox = &mut state.annotation_bboxes[id];
+        bbox.set_corner_points(
+            nalgebra::Vector2::new(x, y),
+            nalgebra::Vector2::new(x + width, y + height),
+        );
+        bbox.interpolated = false;
+        snap_annotation(state, id);
+    }
+}
+
+/// Sortable table of the annotations of the current image
+fn annotations_ui(ui: &mut Ui, state: &mut OculanteState) {
+    selected_annotation_ui(ui, state);
+
+    let order = state.annotation_sort.order(&state.annotation_bboxes);
+    let num_classes = state.label_colors.len() as u32;
+    let editable = state.locked_by.is_none();
+    let mut edited = None;
+    let mut removed = None;
+
+    egui::ScrollArea::horizontal().show(ui, |ui| {
+        egui::Grid::new("annotations").striped(true).show(ui, |ui| {
+            ui.label("");
+            for (column, title) in [
+                (AnnotationColumn::Index, "#"),
+                (AnnotationColumn::Class, "Class"),
+                (AnnotationColumn::X, "X"),
+                (AnnotationColumn::Y, "Y"),
+                (AnnotationColumn::Width, "W"),
+                (AnnotationColumn::Height, "H"),
+                (AnnotationColumn::Confidence, "Conf"),
+                (AnnotationColumn::Track, "Track"),
+            ] {
+                let sort = state.annotation_sort;
+                let text = match (sort.column == column, sort.descending) {
+                    (true, false) => format!("{title} {CARET_UP}"),
+                    (true, true) => format!("{title} {CARET_DOWN}"),
+                    _ => title.to_string(),
+                };
+                if ui.selectable_label(sort.column == column, text).clicked() {
+                    state.annotation_sort.toggle(column);
+                }
+            }
+            ui.end_row();
+
+            for id in order {
+                let color = to_color32(
+                    state.label_colors
+                        [state.annotation_bboxes[id].class_id as usize % state.label_colors.len()],
+                );
+                // Boxes of locked or hidden classes can only be shown or selected
+                let editable = editable
+                    && state
+                        .annotation_visibility
+                        .is_editable(&state.annotation_bboxes[id]);
+                let project = &state.project;
+                let bbox = &mut state.annotation_bboxes[id];
+
+                if ui
+                    .button(if bbox.hidden { EYE_SLASH } else { EYE })
+                    .on_hover_text("Show or hide in the image")
+                    .clicked()
+                {
+                    bbox.hidden = !bbox.hidden;
+                }
+
+                let selected = state.selected_bbox_id == Some(id);
+                if ui.selectable_label(selected, format!("{id}")).clicked() {
+                    state.selected_bbox_id = if selected { None } else { Some(id) };
+                }
+
+                ui.horizontal(|ui| {
+                    ui.set_enabled(editable);
+                    ui.label(RichText::new("■").color(color));
+                    egui::ComboBox::from_id_source(("annotation class", id))
+                        .selected_text(project.class_name(bbox.class_id))
+                        .show_ui(ui, |ui| {
+                            for class_id in 0..num_classes {
+                                ui.selectable_value(
+                                    &mut bbox.class_id,
+                                    class_id,
+                                    project.class_name(class_id),
+                                );
+                            }
+                        });
+                });
+
+                let (mut x, mut y) = (bbox.x_min(), bbox.y_min());
+                let (mut width, mut height) = bbox.size();
+                let mut changed = false;
+                for value in [&mut x, &mut y] {
+                    changed |= ui
+                        .add_enabled(
+                            editable,
+                            egui::DragValue::new(value).speed(1.0).max_decimals(1),
+                        )
+                        .changed();
+                }
+                for value in [&mut width, &mut height] {
+                    changed |= ui
+                        .add_enabled(
+                            editable,
+                            egui::DragValue::new(value)
+                                .speed(1.0)
+                                .max_decimals(1)
+                                .clamp_range(1.0..=f32::MAX),
+                        )
+                        .changed();
+                }
+                if changed {
+                    bbox.set_corner_points(
+                        nalgebra::Vector2::new(x, y),
+                        nalgebra::Vector2::new(x + width, y + height),
+                    );
+                    bbox.interpolated = false;
+                    edited = Some(id);
+                }
+
+                ui.label(
+                    bbox.probability
+                        .map(|p| format!("{p:.2}"))
+                        .unwrap_or("-".into()),
+                );
+                ui.label(
+                    bbox.object_id
+                        .map(|id| format!("{id}"))
+                        .unwrap_or("-".into()),
+                );
+
+                if ui
+                    .add_enabled(editable, egui::Button::new(TRASH))
+                    .on_hover_text("Remove")
+                    .clicked()
+                {
+                    removed = Some(id);
+                }
+                ui.end_row();
+            }
+        });
+    });
+
+    if let Some(id) = edited {
+        snap_annotation(state, id);
+    }
+    if let Some(id) = removed {
+        remove_annotation(state, id);
+        state.send_message("Deleted annotation");
+    }
+}
+
+fn stamp_sizes_ui(ui: &mut Ui, state: &mut OculanteState) {
+    ui.horizontal(|ui| {
+        ui.label("Scale");
+        ui.add(
+            egui::DragValue::new(&mut state.stamp_scale)
+                .speed(0.01)
+                .clamp_range(0.05..=20.0),
+        )
+        .on_hover_text("Scroll with shift held in stamp mode to change");
+    });
+
+    egui::Grid::new("stamp sizes").striped(true).show(ui, |ui| {
+        ui.label("Class");
+        ui.label("Preset");
+        ui.label("Size");
+        ui.end_row();
+        for class_id in 0..state.label_colors.len() as u32 {
+            ui.label(format!("{class_id}"));
+            let mut has_preset = state.project.stamp_sizes.contains_key(&class_id);
+            if ui.checkbox(&mut has_preset, "").changed() {
+                if has_preset {
+                    let size = stamp_size(state, class_id);
+                    let size = (size.0 / state.stamp_scale, size.1 / state.stamp_scale);
+                    state.project.stamp_sizes.insert(class_id, size);
+                } else {
+                    state.project.stamp_sizes.remove(&class_id);
+                }
+            }
+            match state.project.stamp_sizes.get_mut(&class_id) {
+                Some((width, height)) => {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(width).clamp_range(1.0..=f32::MAX));
+                        ui.add(egui::DragValue::new(height).clamp_range(1.0..=f32::MAX));
+                    });
+                }
+                None => {
+                    let (width, height) = stamp_size(state, class_id);
+                    ui.label(format!("{width:.0} x {height:.0}"));
+                }
+            }
+            ui.end_row();
+        }
+    });
+
+    if ui
+        .add_enabled(
+            state.project_folder.is_some(),
+            egui::Button::new("Save to project"),
+        )
+        .on_hover_text("Share the presets with everyone annotating this folder")
+        .clicked()
+    {
+        if let Some(folder) = &state.project_folder {
+            match state.project.save(folder) {
+                Ok(_) => state.send_message("Saved project settings"),
+                Err(e) => state.send_message_err(&format!("Could not save project: {e}")),
+            }
+        }
+    }
+    if ui.button("Reload").clicked() {
+        state.project = state
+            .project_folder
+            .as_deref()
+            .and_then(|f| ProjectSettings::load(f).ok())
+            .unwrap_or_default();
+    }
+}
 
-                            ui.label_i(&format!("{PALETTE} RGBA"));
-                            ui.label(
-                                RichText::new(disp_col_norm(state.sampled_color, 255.))
-                                    .monospace()
-                                    .background_color(Color32::from_rgba_unmultiplied(
-                                        255, 255, 255, 6,
-                                    )),
-                            );
-                            ui.end_row();
+fn tracks_ui(ui: &mut Ui, state: &mut OculanteState) {
+    ui.checkbox(
+        &mut state.persistent_settings.color_by_track,
+        "Colour boxes by track",
+    );
+    ui.checkbox(
+        &mut state.persistent_settings.auto_track_ids,
+        "Assign track ids automatically",
+    )
+    .on_hover_text("Match new boxes with the previous image whenever the annotations are saved");
+
+    if let Some(id) = state.selected_bbox_id {
+        ui.horizontal(|ui| {
+            ui.set_enabled(state.locked_by.is_none());
+            ui.label("Track of selected box");
+            let mut has_id = state.annotation_bboxes[id].object_id.is_some();
+            if ui.checkbox(&mut has_id, "").changed() {
+                let object_id = if has_id {
+                    Some(unused_object_id(state))
+                } else {
+                    None
+                };
+                state.annotation_bboxes[id].object_id = object_id;
+            }
+            if let Some(object_id) = &mut state.annotation_bboxes[id].object_id {
+                ui.add(egui::DragValue::new(object_id));
+            }
+        });
+    }
 
-                            ui.label_i("⊞ Pos");
-                            ui.label(
-                                RichText::new(format!(
-                                    "{:.0},{:.0}",
-                                    state.cursor_relative.x, state.cursor_relative.y
-                                ))
-                                .monospace()
-                                .background_color(
-                                    Color32::from_rgba_unmultiplied(255, 255, 255, 6),
-                                ),
-                            );
-                            ui.end_row();
+    ui.horizontal(|ui| {
+        let editable = state.locked_by.is_none();
+        if tooltip(
+            ui.add_enabled(editable, egui::Button::new("Assign ids")),
+            "Continue the tracks of the previous image",
+            &lookup(
+                &state.persistent_settings.shortcuts,
+                &crate::shortcuts::InputEvent::AssignTrackIds,
+            ),
+            ui,
+        )
+        .clicked()
+        {
+            assign_track_ids(state);
+        }
+        let idle = state.track_operation_job.is_none();
+        if ui
+            .add_enabled(editable && idle, egui::Button::new("Renumber"))
+            .on_hover_text("Number all tracks consecutively in order of appearance")
+            .clicked()
+        {
+            apply_track_operation(state, renumber_tracks);
+        }
+        if ui
+            .button("Refresh")
+            .on_hover_text("Save the annotations and collect the tracks again")
+            .clicked()
+        {
+            write_annotions_to_file(state);
+            invalidate_track_spans(state);
+        }
+    });
 
-                            ui.label_i(" UV");
-                            ui.label(
-                                RichText::new(format!(
-                                    "{:.3},{:.3}",
-                                    uv_center.0,
-                                    1.0 - uv_center.1
-                                ))
-                                .monospace()
-                                .background_color(
-                                    Color32::from_rgba_unmultiplied(255, 255, 255, 6),
-                                ),
-                            );
-                            ui.end_row();
+    update_track_spans(state);
+    if state.track_spans_job.is_some() || state.track_operation_job.is_some() {
+        ui.spinner();
+        ui.ctx().request_repaint();
+    }
+
+    let spans = state.track_spans.clone().unwrap_or_default();
+    let current_index = state.scrubber.index;
+    egui::Grid::new("tracks").striped(true).show(ui, |ui| {
+        ui.label("Id");
+        ui.label("Images");
+        ui.label("Boxes");
+        ui.end_row();
+        for span in &spans {
+            if ui
+                .selectable_label(
+                    (span.first..=span.last).contains(&current_index),
+                    format!("{}", span.object_id),
+                )
+                .on_hover_text("Go to the first image of this track")
+                .clicked()
+                && write_annotions_to_file(state)
+            {
+                let p = state.scrubber.set(span.first);
+                load_image_from_path(&p, state);
+            }
+            ui.label(format!("{}-{}", span.first + 1, span.last + 1));
+            ui.label(format!("{}", span.num_boxes));
+
+            let enabled = state.locked_by.is_none() && state.track_operation_job.is_none();
+            ui.add_enabled_ui(enabled, |ui| {
+                ui.menu_button(DOTS_THREE, |ui| {
+                    if ui
+                        .add_enabled(
+                            span.first < current_index && current_index <= span.last,
+                            egui::Button::new("Split at current image"),
+                        )
+                        .clicked()
+                    {
+                        let new_id = unused_object_id(state);
+                        let (object_id, at) = (span.object_id, current_index);
+                        apply_track_operation(state, move |entries| {
+                            split_track(entries, object_id, at, new_id)
                         });
+                        ui.close_menu();
                     }
+                    ui.menu_button("Merge into", |ui| {
+                        for other in spans.iter().filter(|o| o.object_id != span.object_id) {
+                            if ui.button(format!("{}", other.object_id)).clicked() {
+                                let (from, to) = (span.object_id, other.object_id);
+                                apply_track_operation(state, move |entries| {
+                                    merge_tracks(entries, from, to)
+                                });
+                                ui.close_menu();
+                            }
+                        }
+                    });
                 });
-
-            ui.separator();
-            ui.label(format!(
-                "current_bounding_box_element_under_cursor: {:?}",
-                state.current_bounding_box_element_under_cursor
-            ));
-            ui.separator();
-            ui.label(format!("selected_bbox_id: {:?}", state.selected_bbox_id));
-            ui.separator();
-            ui.label(format!("bbox_edit_mode: {:?}", state.bbox_edit_mode));
-            ui.separator();
-            ui.label(format!("annotation_bboxes: {:?}", state.annotation_bboxes));
-            ui.separator();
-        });
+            });
+            ui.end_row();
+        }
+    });
 }
 
 pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState) {
@@ -378,6 +1616,24 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState) {
                 );
                     ui.end_row();
 
+                ui.checkbox(
+                    &mut state.persistent_settings.show_status_bar,
+                    "Show status bar",
+                )
+                .on_hover_text(
+                    "Show the position in the folder, the boxes of the image and the progress of the folder.",
+                );
+                ui.end_row();
+
+                ui.checkbox(
+                    &mut state.persistent_settings.lock_files,
+                    "Lock images while editing",
+                )
+                .on_hover_text(
+                    "Create a .lock file next to the labels of the open image, so others sharing the folder can't edit them at the same time. Locks expire after 15 minutes without being renewed.",
+                );
+                ui.end_row();
+
                     if ui
                     .checkbox(&mut state.persistent_settings.wrap_folder, "Wrap images at folder boundary")
                     .on_hover_text(
@@ -431,6 +1687,88 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState) {
                     set_title(app, state);
                 }
 
+                ui.checkbox(
+                    &mut state.persistent_settings.two_click_drawing,
+                    "Two-click drawing",
+                )
+                .on_hover_text(
+                    "Draw a new box by clicking its first and then its opposite corner, without dragging.",
+                );
+                ui.end_row();
+                ui.horizontal(|ui| {
+                    ui.label("Aspect ratio with shift");
+                    ui.add(
+                        egui::DragValue::new(&mut state.persistent_settings.draw_aspect_ratio)
+                            .speed(0.01)
+                            .clamp_range(0.01..=100.0),
+                    )
+                    .on_hover_text(
+                        "Width divided by height of new boxes while shift is held. 1 draws squares. Hold alt to draw from the centre.",
+                    );
+                });
+                ui.end_row();
+
+                ui.horizontal(|ui| {
+                    ui.label("Zoom padding");
+                    ui.add(
+                        egui::DragValue::new(&mut state.persistent_settings.zoom_padding)
+                            .speed(0.01)
+                            .clamp_range(0.0..=10.0),
+                    )
+                    .on_hover_text(
+                        "Space around a box when zooming to it, relative to the size of the box.",
+                    );
+                });
+                ui.checkbox(
+                    &mut state.persistent_settings.review_across_images,
+                    "Review across images",
+                )
+                .on_hover_text("Continue the review on the next image after the last box.");
+                ui.end_row();
+
+                ui.horizontal(|ui| {
+                    ui.label("Minimum size of new boxes on screen");
+                    ui.add(
+                        egui::DragValue::new(&mut state.persistent_settings.min_box_screen_size)
+                            .speed(0.1)
+                            .clamp_range(0.0..=100.0)
+                            .suffix(" px"),
+                    )
+                    .on_hover_text(
+                        "New boxes smaller than this on screen, or than the minimum size in image pixels, are discarded.",
+                    );
+                });
+                ui.end_row();
+
+                ui.checkbox(&mut state.persistent_settings.show_loupe, "Show loupe")
+                    .on_hover_text(
+                        "Show a magnified view of the cursor while drawing or resizing a box.",
+                    );
+                ui.horizontal(|ui| {
+                    ui.label("Loupe zoom");
+                    ui.add(
+                        egui::DragValue::new(&mut state.persistent_settings.loupe_zoom)
+                            .speed(0.1)
+                            .clamp_range(2.0..=32.0)
+                            .suffix("x"),
+                    );
+                });
+                ui.end_row();
+
+                ui.checkbox(
+                    &mut state.persistent_settings.show_annotation_tags,
+                    "Show annotation tags",
+                )
+                .on_hover_text("Show the class, confidence and track id above each box.");
+                ui.horizontal(|ui| {
+                    ui.label("Hide tags of boxes smaller than");
+                    ui.add(
+                        egui::DragValue::new(&mut state.persistent_settings.annotation_tag_min_size)
+                            .speed(1.0)
+                            .clamp_range(0.0..=1000.0)
+                            .suffix(" px"),
+                    );
+                });
 
                 }
 
@@ -442,7 +1780,7 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState) {
                     if ui
                     .text_edit_singleline(&mut state.persistent_settings.title_format)
                     .on_hover_text(
-                        "Configure the title. Use {APP}, {VERSION}, {FULLPATH}, {FILENAME} and {RES} as placeholders.",
+                        "Configure the title. Use {APP}, {VERSION}, {FULLPATH}, {FILENAME}, {RES}, {BOXES}, {INDEX} and {TOTAL} as placeholders.",
                     )
                     .changed()
                     {
@@ -523,17 +1861,199 @@ pub fn unframed_button_colored(text: impl Into<String>, is_colored: bool, ui: &m
     }
 }
 
+/// Position in the folder, the current image and the progress of the folder
+pub fn status_bar_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let counts = state.progress.counts;
+    ui.horizontal(|ui| {
+        ui.label(format!(
+            "Image {} / {}",
+            group_digits(state.scrubber.index + 1),
+            group_digits(state.scrubber.entries.len())
+        ));
+        if let Some(dataset) = &state.dataset {
+            let name = dataset.name();
+            let source = dataset.source.clone();
+            ui.separator();
+            ui.label(name);
+            if let DatasetSource::List {
+                file,
+                split: Some(split),
+            } = &source
+            {
+                let mut selected = split.clone();
+                egui::ComboBox::from_id_source("dataset split")
+                    .selected_text(&selected)
+                    .show_ui(ui, |ui| {
+                        for s in SPLITS.iter() {
+                            ui.selectable_value(&mut selected, s.to_string(), *s);
+                        }
+                    });
+                if &selected != split {
+                    open_dataset(
+                        state,
+                        DatasetSource::List {
+                            file: file.clone(),
+                            split: Some(selected),
+                        },
+                    );
+                }
+            }
+            if ui
+                .small_button(X)
+                .on_hover_text("Only show the folder of this image")
+                .clicked()
+            {
+                close_dataset(state);
+            }
+        }
+        ui.separator();
+        ui.label(format!("{} annotated", group_digits(counts.annotated)));
+        ui.label(format!("{} background", group_digits(counts.background)));
+        ui.separator();
+        ui.label(format!("{} boxes", state.annotation_bboxes.len()));
+        ui.separator();
+        let class_id = state.current_label_class;
+        let color = to_color32(state.label_colors[class_id as usize % state.label_colors.len()]);
+        ui.label(RichText::new("■").color(color));
+        ui.label(state.project.class_name(class_id));
+
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            let text = if counts.is_complete() {
+                format!(
+                    "{:.0}% of {} images",
+                    counts.fraction() * 100.,
+                    group_digits(counts.images)
+                )
+            } else {
+                format!("Counting {} / {}", counts.counted, counts.images)
+            };
+            ui.add(
+                egui::ProgressBar::new(counts.fraction())
+                    .desired_width(200.)
+                    .text(text),
+            )
+            .on_hover_text("Images with boxes, marked done or marked as background");
+        });
+    });
+}
+
+/// Choose the order the images are moved through in, and the seed of a shuffle
+fn sort_order_ui(ui: &mut Ui, state: &mut OculanteState) {
+    let mut order = state.project.sort_order;
+    let seed = match order {
+        SortOrder::Shuffle(seed) => seed,
+        _ => 0,
+    };
+    egui::ComboBox::from_id_source("sort order")
+        .selected_text(order.name())
+        .show_ui(ui, |ui| {
+            for o in [
+                SortOrder::Name,
+                SortOrder::Modified,
+                SortOrder::Size,
+                SortOrder::Boxes,
+                SortOrder::LabelsEdited,
+                SortOrder::Shuffle(seed),
+            ]
+            .iter()
+            {
+                ui.selectable_value(&mut order, *o, o.name());
+            }
+        })
+        .response
+        .on_hover_text("Order of the images, saved with the project");
+    if let SortOrder::Shuffle(saved_seed) = order {
+        let sample = (state.progress.counts.images as f32 * 0.05).ceil() as usize;
+        let mut seed = state.shuffle_seed.unwrap_or(saved_seed);
+        let response = ui
+            .add(egui::DragValue::new(&mut seed).prefix("Seed "))
+            .on_hover_text(format!(
+                "The same seed gives the same order, so the first {} images are a \
+                 reproducible 5% sample",
+                group_digits(sample)
+            ));
+        if response.changed() {
+            state.shuffle_seed = Some(seed);
+        }
+        // Every seed reindexes the folder, so only the final one is applied
+        if response.drag_released() || response.lost_focus() {
+            if let Some(seed) = state.shuffle_seed.take() {
+                order = SortOrder::Shuffle(seed);
+            }
+        }
+    }
+    if order != state.project.sort_order {
+        set_sort_order(state, order);
+    }
+}
+
 pub fn scrubber_ui(state: &mut OculanteState, ui: &mut Ui) {
     let len = state.scrubber.len().saturating_sub(1);
 
-    if ui
-        .slider_timeline(&mut state.scrubber.index, 0..=len)
-        .changed()
-    {
-        let p = state.scrubber.set(state.scrubber.index);
-        state.current_path = Some(p.clone());
-        state.player.load(&p, state.message_channel.0.clone());
-    }
+    ui.horizontal(|ui| {
+        sort_order_ui(ui, state);
+
+        let filters = [
+            ImageFilter::All,
+            ImageFilter::Unannotated,
+            ImageFilter::EmptyLabels,
+            ImageFilter::Flagged,
+            ImageFilter::Unlocked,
+        ]
+        .iter()
+        .cloned()
+        .chain((0..state.label_colors.len() as u32).map(ImageFilter::WithClass));
+        egui::ComboBox::from_id_source("image filter")
+            .selected_text(state.image_filter.name(&state.project))
+            .show_ui(ui, |ui| {
+                for filter in filters {
+                    ui.selectable_value(
+                        &mut state.image_filter,
+                        filter,
+                        filter.name(&state.project),
+                    );
+                }
+            });
+
+        if tooltip(
+            ui.button(CARET_LEFT),
+            "Previous matching image",
+            &lookup(
+                &state.persistent_settings.shortcuts,
+                &crate::shortcuts::InputEvent::PreviousFiltered,
+            ),
+            ui,
+        )
+        .clicked()
+        {
+            jump_to_image(state, state.image_filter, false);
+        }
+        if tooltip(
+            ui.button(CARET_RIGHT),
+            "Next matching image",
+            &lookup(
+                &state.persistent_settings.shortcuts,
+                &crate::shortcuts::InputEvent::NextFiltered,
+            ),
+            ui,
+        )
+        .clicked()
+        {
+            jump_to_image(state, state.image_filter, true);
+        }
+        if state.image_search_job.is_some() {
+            ui.spinner();
+        }
+
+        if ui
+            .slider_timeline(&mut state.scrubber.index, 0..=len)
+            .changed()
+        {
+            let p = state.scrubber.set(state.scrubber.index);
+            state.current_path = Some(p.clone());
+            state.player.load(&p, state.message_channel.0.clone());
+        }
+    });
 }
 
 fn keybinding_ui(app: &mut App, state: &mut OculanteState, ui: &mut Ui) {
@@ -704,6 +2224,67 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, _gfx: &m
         }
 
         if state.current_path.is_some() {
+            for (tool, icon, description, event) in [
+                (Tool::Select, CURSOR, "Select and edit boxes", SelectTool),
+                (Tool::Draw, RECTANGLE, "Draw new boxes", DrawTool),
+                (
+                    Tool::Stamp,
+                    STAMP,
+                    "Place boxes of a fixed size with a click",
+                    StampMode,
+                ),
+                (Tool::Pan, HAND, "Move the image", PanTool),
+            ] {
+                // Boxes can't be added to labels someone else is editing
+                let enabled =
+                    state.locked_by.is_none() || !matches!(tool, Tool::Draw | Tool::Stamp);
+                if ui
+                    .add_enabled_ui(enabled, |ui| {
+                        tooltip(
+                            unframed_button_colored(icon, state.tool == tool, ui),
+                            description,
+                            &lookup(&state.persistent_settings.shortcuts, &event),
+                            ui,
+                        )
+                        .clicked()
+                    })
+                    .inner
+                {
+                    state.tool = tool;
+                }
+            }
+
+            let recursive = matches!(
+                state.dataset.as_ref().map(|d| &d.source),
+                Some(DatasetSource::Recursive(_))
+            );
+            if unframed_button_colored(TREE_STRUCTURE, recursive, ui)
+                .on_hover_text("Include the images of all subfolders")
+                .clicked()
+            {
+                if recursive {
+                    close_dataset(state);
+                } else if let Some(folder) = state.project_folder.clone() {
+                    open_dataset(state, DatasetSource::Recursive(folder));
+                }
+            }
+
+            if tooltip(
+                unframed_button_colored(
+                    SQUARES_FOUR,
+                    state.persistent_settings.show_thumbnails,
+                    ui,
+                ),
+                "Thumbnail grid",
+                &lookup(&state.persistent_settings.shortcuts, &ThumbnailGrid),
+                ui,
+            )
+            .clicked()
+            {
+                state.persistent_settings.show_thumbnails =
+                    !state.persistent_settings.show_thumbnails;
+            }
+
             if tooltip(
                 unframed_button(TRASH, ui),
                 "Remove the selected annation",
@@ -712,11 +2293,51 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, _gfx: &m
             )
             .clicked()
             {
-                if let Some(id) = state.selected_bbox_id {
-                    state.annotation_bboxes.remove(id);
-                    state.selected_bbox_id = None;
-                    state.send_message("Deleted annotation");
-                }
+                delete_selected_annotation(state);
+            }
+
+            if tooltip(
+                unframed_button_colored(FLAG, state.keyframe.is_some(), ui),
+                "Use the selected annotation as keyframe",
+                &lookup(&state.persistent_settings.shortcuts, &SetKeyframe),
+                ui,
+            )
+            .clicked()
+            {
+                set_keyframe(state);
+            }
+
+            if tooltip(
+                unframed_button(PATH, ui),
+                "Interpolate from the keyframe to the selected annotation",
+                &lookup(&state.persistent_settings.shortcuts, &InterpolateKeyframe),
+                ui,
+            )
+            .clicked()
+            {
+                interpolate_from_keyframe(state);
+            }
+
+            if tooltip(
+                unframed_button(MAGNIFYING_GLASS_PLUS, ui),
+                "Zoom to the selected annotation",
+                &lookup(&state.persistent_settings.shortcuts, &ZoomToAnnotation),
+                ui,
+            )
+            .clicked()
+            {
+                zoom_to_selected_annotation(state);
+            }
+
+            if tooltip(
+                unframed_button(BINOCULARS, ui),
+                "Review: zoom to the next annotation",
+                &lookup(&state.persistent_settings.shortcuts, &ReviewNext),
+                ui,
+            )
+            .clicked()
+            {
+                review_step(state, true);
             }
         }
 
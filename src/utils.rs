@@ -5,6 +5,7 @@ use arboard::Clipboard;
 use img_parts::{Bytes, DynImage, ImageEXIF};
 use log::{debug, error, info};
 use nalgebra::{clamp, Vector2};
+use notan::graphics::color::Color;
 use notan::graphics::Texture;
 use notan::prelude::{App, Graphics, TextureFilter};
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
@@ -13,9 +14,9 @@ use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 
 use std::io::Cursor;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::Result;
 use image::{self};
@@ -23,10 +24,24 @@ use image::{EncodableLayout, Rgba, RgbaImage};
 use std::sync::mpsc::{self};
 use std::sync::mpsc::{Receiver, Sender};
 
-use crate::appstate::{Message, OculanteState};
+use crate::appstate::{
+    AnnoationBoundingBox, BoundingBoxPart, Message, OculanteState, ReviewWalk, SnapTargets,
+};
+use crate::batch::{apply_batch, plan_batch, revert_batch};
 use crate::cache::Cache;
+use crate::dataset::{Dataset, DatasetSource};
+use crate::folder_index::{FolderIndex, IndexSource, LabelSummary};
+use crate::image_filter::{find_image, ImageFilter, ImageSearch};
 use crate::image_loader::open_image;
+use crate::job::Job;
+use crate::lock::{acquire_lock, HeldLock, LOCK_REFRESH_INTERVAL};
+use crate::progress::ProgressCounter;
+use crate::project::{project_folder, read_class_names, ImageStatus, ProjectSettings, SortOrder};
+use crate::query::{run_query, DatasetQuery, QueryRun};
+use crate::scrubber::Scrubber;
 use crate::shortcuts::{lookup, InputEvent};
+use crate::tracks::assign_track_ids;
+use crate::yolo_labels::{Labels, Unnormaliser};
 
 pub const SUPPORTED_EXTENSIONS: &[&str] = &[
     "bmp",
@@ -560,7 +575,9 @@ pub fn clipboard_copy(img: &RgbaImage) {
 
 pub fn prev_image(state: &mut OculanteState) {
     // First save the previous image annotations
-    write_annotions_to_file(state);
+    if !write_annotions_to_file(state) {
+        return;
+    }
 
     if let Some(img_location) = state.current_path.as_mut() {
         let next_img = state.scrubber.prev();
@@ -581,9 +598,372 @@ pub fn load_image_from_path(p: &Path, state: &mut OculanteState) {
     state.current_path = Some(p.to_owned());
 }
 
+/// Go to the next or previous image matching a filter
+pub fn jump_to_image(state: &mut OculanteState, filter: ImageFilter, forward: bool) {
+    if state.current_path.is_none() {
+        return;
+    }
+    // The current annotations decide whether this image matches next time
+    if !write_annotions_to_file(state) {
+        return;
+    }
+
+    let index_ready = state
+        .folder_index
+        .as_ref()
+        .map(|i| i.ready)
+        .unwrap_or_default();
+    if filter.reads_files() || !index_ready {
+        let entries = state.scrubber.entries.clone();
+        let (index, wrap) = (state.scrubber.index, state.scrubber.wrap);
+        let min_box_size = state.persistent_settings.min_box_size;
+        let from = state.current_path.clone().unwrap_or_default();
+        state.image_search_job = Some(Job::spawn(move || ImageSearch {
+            filter,
+            from,
+            found: find_image(&entries, None, index, forward, wrap, filter, min_box_size)
+                .map(|i| entries[i].clone()),
+        }));
+        return;
+    }
+
+    match find_image(
+        &state.scrubber.entries,
+        state.folder_index.as_ref().map(|i| &i.labels),
+        state.scrubber.index,
+        forward,
+        state.scrubber.wrap,
+        filter,
+        state.persistent_settings.min_box_size,
+    ) {
+        Some(index) if index != state.scrubber.index => {
+            let p = state.scrubber.set(index);
+            load_image_from_path(&p, state);
+        }
+        _ => state.send_message(&format!(
+            "No other image matches '{}'",
+            filter.name(&state.project)
+        )),
+    }
+}
+
+/// Go to the image found by a search in the background, unless another image was opened
+/// in the meantime
+pub fn update_image_search(state: &mut OculanteState) {
+    let search = match state.image_search_job.as_ref().and_then(|job| job.poll()) {
+        Some(search) => search,
+        None => return,
+    };
+    state.image_search_job = None;
+    if state.current_path.as_ref() != Some(&search.from) {
+        return;
+    }
+    let index = search
+        .found
+        .as_ref()
+        .filter(|p| **p != search.from)
+        .and_then(|p| state.scrubber.entries.iter().position(|e| e == p));
+    match index {
+        Some(index) => {
+            let p = state.scrubber.set(index);
+            load_image_from_path(&p, state);
+        }
+        None => state.send_message(&format!(
+            "No other image matches '{}'",
+            search.filter.name(&state.project)
+        )),
+    }
+}
+
+/// Restrict the image sequence to the images matching the typed query
+pub fn apply_query(state: &mut OculanteState) {
+    let current_path = match &state.current_path {
+        Some(p) => p.clone(),
+        None => return,
+    };
+    let expression = state.query_text.trim().to_string();
+    if expression.is_empty() {
+        clear_query(state);
+        return;
+    }
+    // The current annotations should be part of the result
+    write_annotions_to_file(state);
+
+    let num_classes = if state.project.class_names.is_empty() {
+        state.label_colors.len()
+    } else {
+        state.project.class_names.len()
+    } as u32;
+    let entries = scrubber_for(state, &current_path).entries;
+    // Every label file is read, so this is done in the background
+    state.query_job = Some(Job::spawn(move || QueryRun {
+        matches: run_query(&expression, &entries, num_classes),
+        expression,
+        entries,
+    }));
+}
+
+/// Restrict the image sequence once the query has run
+pub fn update_query(state: &mut OculanteState) {
+    let run = match state.query_job.as_ref().and_then(|job| job.poll()) {
+        Some(run) => run,
+        None => return,
+    };
+    state.query_job = None;
+    let current_path = match &state.current_path {
+        Some(p) => p.clone(),
+        None => return,
+    };
+    match run.matches {
+        Ok(matches) => {
+            state.send_message(&format!(
+                "{} of {} images match",
+                matches.len(),
+                run.entries.len()
+            ));
+            state.scrubber.entries = run.entries;
+            state.scrubber.index = state
+                .scrubber
+                .entries
+                .iter()
+                .position(|p| *p == current_path)
+                .unwrap_or_default();
+            state.scrubber.restrict(&matches);
+            retain_shown_selection(state);
+            state.dataset_query = Some(DatasetQuery {
+                expression: run.expression,
+                matches,
+            });
+        }
+        Err(e) => state.send_message_err(&format!("Invalid query: {e}")),
+    }
+}
+
+/// Unselect the thumbnails of images which are no longer in the image sequence
+fn retain_shown_selection(state: &mut OculanteState) {
+    if state.thumbnail_selection.is_empty() && state.thumbnail_anchor.is_none() {
+        return;
+    }
+    let shown: HashSet<&PathBuf> = state.scrubber.entries.iter().collect();
+    state.thumbnail_selection.retain(|p| shown.contains(p));
+    if let Some(anchor) = &state.thumbnail_anchor {
+        if !shown.contains(anchor) {
+            state.thumbnail_anchor = None;
+        }
+    }
+}
+
+/// Show all images of the folder again
+pub fn clear_query(state: &mut OculanteState) {
+    state.dataset_query = None;
+    state.query_job = None;
+    if let Some(p) = &state.current_path {
+        state.scrubber = scrubber_for(state, p);
+    }
+}
+
+/// The images to move through from an image: its dataset, or else its folder
+pub fn scrubber_for(state: &OculanteState, path: &Path) -> Scrubber {
+    let mut scrubber = match (&state.dataset, &state.folder_index) {
+        // The index has the images in the sort order of the project
+        (_, Some(index)) if index.covers(path) => {
+            Scrubber::with_entries(index.entries.clone(), path)
+        }
+        (Some(dataset), _) if dataset.entries.iter().any(|p| p == path) => {
+            Scrubber::with_entries(dataset.entries.clone(), path)
+        }
+        // The scrubber is filled in once the folder has been read
+        (_, Some(index)) if index.will_cover(path) => {
+            Scrubber::with_entries(vec![path.to_path_buf()], path)
+        }
+        _ => Scrubber::new(path),
+    };
+    scrubber.wrap = state.persistent_settings.wrap_folder;
+    scrubber
+}
+
+/// Move through the images of a dataset, from the current image if it is part of it
+pub fn open_dataset(state: &mut OculanteState, source: DatasetSource) {
+    let dataset = match Dataset::open(source) {
+        Ok(dataset) => dataset,
+        Err(e) => {
+            state.send_message_err(&format!("Can't open dataset: {e}"));
+            return;
+        }
+    };
+    if state.current_path.is_some() && !write_annotions_to_file(state) {
+        return;
+    }
+    state.send_message(&format!(
+        "{} images in {}",
+        dataset.entries.len(),
+        dataset.name()
+    ));
+    let current = state
+        .current_path
+        .clone()
+        .filter(|p| dataset.entries.contains(p));
+    let first = dataset.entries[0].clone();
+    state.dataset = Some(dataset);
+    state.dataset_query = None;
+    state.query_job = None;
+    // The project moves to the root of the dataset
+    state.project_folder = None;
+    match current {
+        Some(p) => {
+            update_project(state);
+            state.scrubber = scrubber_for(state, &p);
+        }
+        None => load_image_from_path(&first, state),
+    }
+}
+
+/// Go back to moving through the folder of the current image
+pub fn close_dataset(state: &mut OculanteState) {
+    state.dataset = None;
+    state.dataset_query = None;
+    state.query_job = None;
+    update_project(state);
+    if let Some(p) = state.current_path.clone() {
+        state.scrubber = scrubber_for(state, &p);
+    }
+}
+
+/// Work out what the batch operation would change, for confirmation
+pub fn preview_batch(state: &mut OculanteState) {
+    if state.current_path.is_none() {
+        return;
+    }
+    // The current annotations are part of the batch
+    write_annotions_to_file(state);
+
+    let expression = state.batch_expression.trim();
+    match plan_batch(expression, state.batch_action, &state.scrubber.entries) {
+        Ok(plan) => state.batch_plan = Some(plan),
+        Err(e) => {
+            state.batch_plan = None;
+            state.send_message_err(&format!("Invalid batch expression: {e:#}"));
+        }
+    }
+}
+
+/// Write the previewed batch operation to all label files
+pub fn run_batch(state: &mut OculanteState) {
+    let (plan, folder) = match (state.batch_plan.take(), state.project_folder.clone()) {
+        (Some(plan), Some(folder)) => (plan, folder),
+        _ => return,
+    };
+    match apply_batch(&plan, &folder) {
+        Ok(applied) => {
+            state.send_message(&format!(
+                "Changed {} boxes in {} images",
+                applied.num_boxes, applied.num_images
+            ));
+            if !applied.locked.is_empty() {
+                state.send_message_warn(&format!(
+                    "Skipped {} images someone else is editing: {}",
+                    applied.locked.len(),
+                    file_names(&applied.locked)
+                ));
+            }
+            state.batch_backup = Some(applied.backup_folder);
+        }
+        Err(e) => state.send_message_err(&format!("Batch operation failed: {e}")),
+    }
+    state.thumbnails.invalidate_all();
+    start_folder_index(state);
+    load_annotations_from_file(state);
+}
+
+/// The names of a few files for a message
+pub fn file_names(paths: &[PathBuf]) -> String {
+    const SHOWN: usize = 5;
+    let mut names: Vec<String> = paths
+        .iter()
+        .take(SHOWN)
+        .map(|p| {
+            p.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    if paths.len() > SHOWN {
+        names.push(format!("and {} more", paths.len() - SHOWN));
+    }
+    names.join(", ")
+}
+
+/// Restore the label files from before the last batch operation
+pub fn revert_last_batch(state: &mut OculanteState) {
+    let backup_folder = match state.batch_backup.take() {
+        Some(folder) => folder,
+        None => return,
+    };
+    match revert_batch(&backup_folder) {
+        Ok(reverted) => {
+            state.send_message(&format!("Restored {} label files", reverted.restored));
+            if !reverted.edited.is_empty() {
+                state.send_message_warn(&format!(
+                    "Kept {} label files edited after the batch operation: {}",
+                    reverted.edited.len(),
+                    file_names(&reverted.edited)
+                ));
+            }
+        }
+        Err(e) => {
+            state.send_message_err(&format!("Can't revert batch operation: {e}"));
+            state.batch_backup = Some(backup_folder);
+        }
+    }
+    state.thumbnails.invalidate_all();
+    start_folder_index(state);
+    load_annotations_from_file(state);
+}
+
+/// Remember that the current image has been opened
+pub fn mark_visited(state: &mut OculanteState) {
+    if let (Some(folder), Some(p)) = (&state.project_folder, &state.current_path) {
+        if state.project.image_status(folder, p) == ImageStatus::Unvisited {
+            state.progress.set_status(p, ImageStatus::Visited);
+            let visited = [p.clone()];
+            if let Err(e) = state
+                .project
+                .save_image_status(folder, &visited, ImageStatus::Visited)
+            {
+                debug!("Can't save project: {e}");
+            }
+        }
+    }
+}
+
+/// Set the status of images, like the ones selected in the thumbnail grid
+pub fn set_image_status(state: &mut OculanteState, image_paths: &[PathBuf], status: ImageStatus) {
+    let folder = match &state.project_folder {
+        Some(folder) => folder.clone(),
+        None => return,
+    };
+    for image_path in image_paths {
+        state.progress.set_status(image_path, status);
+    }
+    match state
+        .project
+        .save_image_status(&folder, image_paths, status)
+    {
+        Ok(_) => state.send_message(&format!(
+            "Marked {} images as {}",
+            image_paths.len(),
+            status.name()
+        )),
+        Err(e) => state.send_message_err(&format!("Could not save project: {e}")),
+    }
+}
+
 pub fn last_image(state: &mut OculanteState) {
     // First save the previous image annotations
-    write_annotions_to_file(state);
+    if !write_annotions_to_file(state) {
+        return;
+    }
 
     if let Some(img_location) = state.current_path.as_mut() {
         let last = state.scrubber.len().saturating_sub(1);
@@ -601,7 +981,9 @@ pub fn last_image(state: &mut OculanteState) {
 
 pub fn first_image(state: &mut OculanteState) {
     // First save the previous image annotations
-    write_annotions_to_file(state);
+    if !write_annotions_to_file(state) {
+        return;
+    }
 
     if let Some(img_location) = state.current_path.as_mut() {
         let next_img = state.scrubber.set(0);
@@ -618,7 +1000,9 @@ pub fn first_image(state: &mut OculanteState) {
 
 pub fn next_image(state: &mut OculanteState) {
     // First save the previous image annotations
-    write_annotions_to_file(state);
+    if !write_annotions_to_file(state) {
+        return;
+    }
 
     if let Some(img_location) = state.current_path.as_mut() {
         let next_img = state.scrubber.next();
@@ -654,7 +1038,10 @@ pub fn set_title(app: &mut App, state: &mut OculanteState) {
             "{RES}",
             &format!("{}x{}", state.image_dimension.0, state.image_dimension.1),
             10,
-        );
+        )
+        .replacen("{BOXES}", &state.annotation_bboxes.len().to_string(), 10)
+        .replacen("{INDEX}", &(state.scrubber.index + 1).to_string(), 10)
+        .replacen("{TOTAL}", &state.scrubber.entries.len().to_string(), 10);
 
     if state.persistent_settings.zen_mode {
         title_string.push_str(&format!(
@@ -663,7 +1050,24 @@ pub fn set_title(app: &mut App, state: &mut OculanteState) {
         ));
     }
 
-    app.window().set_title(&title_string);
+    // Only talk to the window if something changed, as this runs every frame
+    if title_string != state.window_title {
+        app.window().set_title(&title_string);
+        state.window_title = title_string;
+    }
+}
+
+/// Format a number with a space between groups of three digits, like 2 000
+pub fn group_digits(number: usize) -> String {
+    let digits = number.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(' ');
+        }
+        grouped.push(digit);
+    }
+    grouped
 }
 
 pub fn fit(oldvalue: f32, oldmin: f32, oldmax: f32, newmin: f32, newmax: f32) -> f32 {
@@ -684,30 +1088,753 @@ pub fn toggle_zen_mode(state: &mut OculanteState, app: &mut App) {
 pub fn set_label_class(state: &mut OculanteState, class_id: u32) {
     state.current_label_class = class_id;
 
-    if let Some(seletected_bbox_id) = state.selected_bbox_id {
-        state.annotation_bboxes[seletected_bbox_id].class_id = class_id;
+    if let Some(bbox) = selected_editable_annotation(state) {
+        bbox.class_id = class_id;
     }
 }
 
 pub fn get_labels_filename(state: &OculanteState) -> PathBuf {
-    let mut labels_filename = state.current_path.clone().unwrap();
-    labels_filename.set_extension("txt");
+    labels_filename_for(&state.current_path.clone().unwrap())
+}
+
+/// The topmost visible annotation containing the image position `p`
+pub fn annotation_at(state: &OculanteState, p: Vector2<f32>) -> Option<usize> {
+    state
+        .annotation_bboxes
+        .iter()
+        .rposition(|b| state.annotation_visibility.is_visible(b) && b.contains((p.x, p.y)))
+}
+
+/// Add a copy of an annotation, slightly offset, and select it
+pub fn duplicate_annotation(state: &mut OculanteState, id: usize) {
+    let mut bbox = state.annotation_bboxes[id];
+    bbox.set_center(bbox.center() + Vector2::new(10.0, 10.0));
+    bbox.object_id = None;
+    bbox.interpolated = false;
+    state.annotation_bboxes.push(bbox);
+    state.selected_bbox_id = Some(state.annotation_bboxes.len() - 1);
+}
+
+/// Move an annotation to the end of the list, where it is drawn last, or to the start
+pub fn reorder_annotation(state: &mut OculanteState, id: usize, to_front: bool) {
+    let bbox = state.annotation_bboxes.remove(id);
+    if to_front {
+        state.annotation_bboxes.push(bbox);
+        state.selected_bbox_id = Some(state.annotation_bboxes.len() - 1);
+    } else {
+        state.annotation_bboxes.insert(0, bbox);
+        state.selected_bbox_id = Some(0);
+    }
+}
+
+/// Centre the view on an annotation, with some padding around it
+pub fn zoom_to_annotation(state: &mut OculanteState, id: usize) {
+    let bbox = state.annotation_bboxes[id];
+    let margin = 1.0 + state.persistent_settings.zoom_padding;
+    let scale = (state.window_size.x / (bbox.width() * margin))
+        .min(state.window_size.y / (bbox.height() * margin))
+        .min(50.0);
+    if scale.is_finite() && scale > 0.0 {
+        crate::set_zoom(scale, None, state);
+        state.image_geometry.offset = state.window_size / 2.0 - bbox.center() * scale;
+    }
+}
+
+/// Zoom to the selected annotation
+pub fn zoom_to_selected_annotation(state: &mut OculanteState) {
+    match state.selected_bbox_id {
+        Some(id) if id < state.annotation_bboxes.len() => zoom_to_annotation(state, id),
+        _ => state.send_message_err("Select a box to zoom to"),
+    }
+}
+
+/// Select and zoom to the next visible box. After the last box, the walk continues
+/// on the next image if enabled.
+pub fn review_step(state: &mut OculanteState, forward: bool) {
+    let visible: Vec<usize> = (0..state.annotation_bboxes.len())
+        .filter(|id| {
+            state
+                .annotation_visibility
+                .is_visible(&state.annotation_bboxes[*id])
+        })
+        .collect();
+    let next = match (state.selected_bbox_id, forward) {
+        (Some(selected), true) => visible.iter().find(|id| **id > selected),
+        (Some(selected), false) => visible.iter().rev().find(|id| **id < selected),
+        (None, true) => visible.first(),
+        (None, false) => visible.last(),
+    };
+
+    if let Some(id) = next.cloned() {
+        state.selected_bbox_id = Some(id);
+        zoom_to_annotation(state, id);
+    } else if state.persistent_settings.review_across_images {
+        review_next_image(
+            state,
+            ReviewWalk {
+                forward,
+                images_visited: 1,
+            },
+        );
+    } else {
+        state.send_message("Reviewed all boxes of this image");
+    }
+}
+
+fn review_next_image(state: &mut OculanteState, walk: ReviewWalk) {
+    if walk.images_visited > state.scrubber.entries.len() {
+        state.send_message("No boxes left to review");
+        return;
+    }
+    let previous_path = state.current_path.clone();
+    if walk.forward {
+        next_image(state);
+    } else {
+        prev_image(state);
+    }
+    if state.current_path == previous_path {
+        state.send_message("Reviewed all images");
+    } else {
+        state.review_walk = Some(walk);
+    }
+}
+
+/// Continue a review walk on a newly loaded image
+pub fn continue_review_walk(state: &mut OculanteState, walk: ReviewWalk) {
+    let mut visible = (0..state.annotation_bboxes.len()).filter(|id| {
+        state
+            .annotation_visibility
+            .is_visible(&state.annotation_bboxes[*id])
+    });
+    let first = if walk.forward {
+        visible.next()
+    } else {
+        visible.last()
+    };
+    match first {
+        Some(id) => {
+            state.selected_bbox_id = Some(id);
+            zoom_to_annotation(state, id);
+        }
+        None => review_next_image(
+            state,
+            ReviewWalk {
+                images_visited: walk.images_visited + 1,
+                ..walk
+            },
+        ),
+    }
+}
+
+/// How close, in screen pixels, an edge has to come to a line to snap to it
+const SNAP_DISTANCE: f32 = 8.0;
+
+/// The lines an edited box snaps to: the image border and the edges of the other visible boxes
+pub fn snap_targets(state: &OculanteState, edited_id: Option<usize>) -> SnapTargets {
+    if !state.persistent_settings.snap_to_edges {
+        return SnapTargets::default();
+    }
+    let mut targets = SnapTargets {
+        xs: vec![0.0, state.image_dimension.0 as f32],
+        ys: vec![0.0, state.image_dimension.1 as f32],
+        distance: SNAP_DISTANCE / state.image_geometry.scale,
+    };
+    for (id, bbox) in state.annotation_bboxes.iter().enumerate() {
+        if Some(id) != edited_id && state.annotation_visibility.is_visible(bbox) {
+            targets.xs.extend([bbox.x_min(), bbox.x_max()]);
+            targets.ys.extend([bbox.y_min(), bbox.y_max()]);
+        }
+    }
+    targets
+}
+
+/// Round the corners of an annotation to whole pixels, if enabled
+pub fn snap_annotation(state: &mut OculanteState, id: usize) {
+    if state.persistent_settings.snap_to_pixels {
+        if let Some(bbox) = state.annotation_bboxes.get_mut(id) {
+            bbox.round_to_pixels();
+        }
+    }
+}
+
+/// Position and size of a box in pixels and in normalised units, for display
+pub fn annotation_dimensions(state: &OculanteState, bbox: &AnnoationBoundingBox) -> String {
+    let label = bbox.to_label(state.image_dimension.0, state.image_dimension.1);
+    format!(
+        "x {:.1} y {:.1} w {:.1} h {:.1}\n{:.4} {:.4} {:.4} {:.4}",
+        bbox.x_min(),
+        bbox.y_min(),
+        bbox.width(),
+        bbox.height(),
+        label.x_centre,
+        label.y_centre,
+        label.width,
+        label.height
+    )
+}
+
+/// Remove an annotation, keeping the selection on the same box
+pub fn remove_annotation(state: &mut OculanteState, id: usize) {
+    state.annotation_bboxes.remove(id);
+    state.selected_bbox_id = match state.selected_bbox_id {
+        Some(selected) if selected == id => None,
+        Some(selected) if selected > id => Some(selected - 1),
+        selected => selected,
+    };
+}
+
+/// Delete the selected annotation, unless its class is locked or hidden
+pub fn delete_selected_annotation(state: &mut OculanteState) {
+    if selected_editable_annotation(state).is_none() {
+        return;
+    }
+    if let Some(id) = state.selected_bbox_id {
+        remove_annotation(state, id);
+        state.send_message("Deleted annotation");
+    }
+}
+
+/// The selected annotation, unless its class is locked or hidden or someone else is editing
+/// the labels
+fn selected_editable_annotation(state: &mut OculanteState) -> Option<&mut AnnoationBoundingBox> {
+    if state.locked_by.is_some() {
+        return None;
+    }
+    let visibility = &state.annotation_visibility;
+    let bboxes = &mut state.annotation_bboxes;
+    state
+        .selected_bbox_id
+        .and_then(move |id| bboxes.get_mut(id))
+        .filter(|bbox| visibility.is_editable(bbox))
+}
+
+/// Move the selected annotation by `delta` image pixels
+pub fn move_selected_annotation(state: &mut OculanteState, delta: Vector2<f32>) {
+    if let Some(bbox) = selected_editable_annotation(state) {
+        bbox.set_center(bbox.center() + delta);
+        bbox.interpolated = false;
+    }
+}
+
+/// Move one edge of the selected annotation by `delta` image pixels.
+/// The box is kept at least one pixel wide.
+pub fn move_selected_annotation_edge(state: &mut OculanteState, part: BoundingBoxPart, delta: f32) {
+    if let Some(bbox) = selected_editable_annotation(state) {
+        match part {
+            BoundingBoxPart::EdgeLeft => {
+                bbox.set_x_min((bbox.x_min() + delta).min(bbox.x_max() - 1.0))
+            }
+            BoundingBoxPart::EdgeRight => {
+                bbox.set_x_max((bbox.x_max() + delta).max(bbox.x_min() + 1.0))
+            }
+            BoundingBoxPart::EdgeTop => {
+                bbox.set_y_min((bbox.y_min() + delta).min(bbox.y_max() - 1.0))
+            }
+            BoundingBoxPart::EdgeBottom => {
+                bbox.set_y_max((bbox.y_max() + delta).max(bbox.y_min() + 1.0))
+            }
+            _ => {}
+        }
+        bbox.interpolated = false;
+    }
+}
+
+/// Select the next (or previous) annotation, wrapping around at the end
+pub fn cycle_selected_annotation(state: &mut OculanteState, forward: bool) {
+    let num_bboxes = state.annotation_bboxes.len();
+    if num_bboxes == 0 {
+        return;
+    }
+    let mut id = state.selected_bbox_id;
+    // Skip boxes that can not be edited, at most once around
+    for _ in 0..num_bboxes {
+        let next = match (id, forward) {
+            (Some(id), true) => (id + 1) % num_bboxes,
+            (Some(id), false) => (id + num_bboxes - 1) % num_bboxes,
+            (None, true) => 0,
+            (None, false) => num_bboxes - 1,
+        };
+        if state
+            .annotation_visibility
+            .is_editable(&state.annotation_bboxes[next])
+        {
+            state.selected_bbox_id = Some(next);
+            return;
+        }
+        id = Some(next);
+    }
+}
+
+/// How often the label file of the current image is checked for changes by others
+const LABEL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Overlap above which boxes of the same class are the same box when merging labels
+const SAME_BOX_IOU: f32 = 0.9;
+
+/// Distance in pixels the edges of a box can move by rounding, without the box being edited
+const UNCHANGED_BOX_TOLERANCE: f32 = 0.5;
+
+/// Size of a stamped box if nothing else is known about its class
+const DEFAULT_STAMP_SIZE: f32 = 32.0;
+
+/// Load the project settings if the current image is in another folder than before.
+/// Images of a dataset share the project in the root of the dataset.
+pub fn update_project(state: &mut OculanteState) {
+    let in_dataset = match (&state.dataset, &state.current_path) {
+        (Some(dataset), Some(p)) => dataset.entries.contains(p),
+        _ => false,
+    };
+    if !in_dataset {
+        state.dataset = None;
+    }
+    let folder = match &state.dataset {
+        Some(dataset) => Some(dataset.root()),
+        None => state.current_path.as_deref().and_then(project_folder),
+    };
+    if folder != state.project_folder {
+        state.project = folder
+            .as_deref()
+            .and_then(|f| ProjectSettings::load(f).ok())
+            .unwrap_or_default();
+        if state.project.class_names.is_empty() {
+            if let Some(folder) = &folder {
+                state.project.class_names = read_class_names(folder);
+            }
+        }
+        if state.project.class_names.is_empty() {
+            if let Some(dataset) = &state.dataset {
+                state.project.class_names = dataset.class_names.clone();
+            }
+        }
+        state.project_folder = folder;
+        state.dataset_query = None;
+        state.query_job = None;
+        state.batch_plan = None;
+        state.batch_backup = None;
+        state.thumbnail_selection.clear();
+        state.thumbnail_anchor = None;
+        start_folder_index(state);
+    }
+}
+
+/// Index the images of the project in the background, replacing the previous index
+pub fn start_folder_index(state: &mut OculanteState) {
+    let source = match (&state.dataset, &state.project_folder) {
+        (Some(dataset), _) => Some(IndexSource::Entries(dataset.entries.clone())),
+        (None, Some(folder)) => Some(IndexSource::Folder(folder.clone())),
+        (None, None) => None,
+    };
+    let order = state.project.sort_order;
+    state.folder_index = source.map(|source| FolderIndex::new(source, order));
+    // The images are counted once the index has found them
+    state.progress = ProgressCounter::new(state.project_folder.as_deref(), vec![]);
+}
+
+/// Move through the images of the project in another order, which is saved with the project
+pub fn set_sort_order(state: &mut OculanteState, order: SortOrder) {
+    state.project.sort_order = order;
+    if let Some(folder) = &state.project_folder {
+        if let Err(e) = state.project.save(folder) {
+            debug!("Can't save project: {e}");
+        }
+    }
+    start_folder_index(state);
+}
+
+/// Take in what the folder index found since the last frame. Returns whether anything changed.
+pub fn update_folder_index(state: &mut OculanteState) -> bool {
+    let changes = match &mut state.folder_index {
+        Some(index) => index.poll(),
+        None => return false,
+    };
+    let changed = changes.entries || !changes.labels.is_empty();
+    if changes.entries {
+        let entries = state
+            .folder_index
+            .as_ref()
+            .map(|i| i.entries.clone())
+            .unwrap_or_default();
+        state.progress = ProgressCounter::new(state.project_folder.as_deref(), entries);
+        if let Some(p) = state.current_path.clone() {
+            state.scrubber = scrubber_for(state, &p);
+            if let Some(query) = &state.dataset_query {
+                state.scrubber.restrict(&query.matches);
+            }
+            retain_shown_selection(state);
+        }
+    }
+    for p in changes.labels {
+        // The current label file is checked right away
+        if state.current_path.as_ref() == Some(&p) {
+            state.labels_checked = None;
+        }
+        let boxes = state
+            .folder_index
+            .as_ref()
+            .and_then(|i| i.labels.get(&p))
+            .map(|s| s.boxes)
+            .unwrap_or_default();
+        state.progress.set_boxes(&p, boxes);
+        state.thumbnails.invalidate(&p);
+    }
+    changed
+}
 
-    return labels_filename;
+/// Size of a stamped box: the preset of the project, or the size of the last box of this class
+pub fn stamp_size(state: &OculanteState, class_id: u32) -> (f32, f32) {
+    let (width, height) = state
+        .project
+        .stamp_sizes
+        .get(&class_id)
+        .or(state.remembered_box_sizes.get(&class_id))
+        .cloned()
+        .unwrap_or((DEFAULT_STAMP_SIZE, DEFAULT_STAMP_SIZE));
+    (width * state.stamp_scale, height * state.stamp_scale)
+}
+
+/// Place a box of the stamp size centred on the cursor and select it. Near the border the
+/// box is moved to stay inside the image.
+pub fn stamp_annotation(state: &mut OculanteState) {
+    let (width, height) = stamp_size(state, state.current_label_class);
+    let mut bbox = AnnoationBoundingBox::from_center(
+        state.cursor_relative.x,
+        state.cursor_relative.y,
+        width,
+        height,
+        state.current_label_class,
+    );
+    bbox.clamp_to_image(
+        state.image_dimension.0 as f32,
+        state.image_dimension.1 as f32,
+    );
+    state.annotation_bboxes.push(bbox);
+    state.selected_bbox_id = Some(state.annotation_bboxes.len() - 1);
+    snap_annotation(state, state.annotation_bboxes.len() - 1);
+}
+
+/// The YOLO label file of an image. It is next to the image, unless the image is in an
+/// `images` folder with a `labels` folder beside it, as in Ultralytics datasets.
+pub fn labels_filename_for(image_path: &Path) -> PathBuf {
+    ultralytics_labels_filename(image_path).unwrap_or_else(|| image_path.with_extension("txt"))
+}
+
+/// `data/images/train/a.jpg` as `data/labels/train/a.txt`, if `data/labels` exists
+fn ultralytics_labels_filename(image_path: &Path) -> Option<PathBuf> {
+    let components: Vec<Component> = image_path.components().collect();
+    let (_, folders) = components.split_last()?;
+    let images = folders.iter().rposition(|c| c.as_os_str() == "images")?;
+    let mut labels_folder: PathBuf = components[..images].iter().collect();
+    labels_folder.push("labels");
+    if !labels_folder.is_dir() {
+        return None;
+    }
+    let labels_file: PathBuf = components[images + 1..].iter().collect();
+    Some(labels_folder.join(labels_file).with_extension("txt"))
+}
+
+/// The boxes in the label file of the current image
+fn read_annotations(state: &OculanteState) -> Vec<AnnoationBoundingBox> {
+    match Labels::from_file(get_labels_filename(state)) {
+        Ok(labels) => labels
+            .unnormalise(state.image_dimension)
+            .iter()
+            .map(AnnoationBoundingBox::from_label)
+            .collect(),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                state.send_message_err(&format!("Can't read the labels: {e}"));
+            }
+            vec![]
+        }
+    }
+}
+
+/// The current annotations as they are written to the label file
+fn current_labels(state: &OculanteState) -> Labels {
+    let (width, height) = state.image_dimension;
+    Labels {
+        labels: state
+            .annotation_bboxes
+            .iter()
+            .map(|b| b.to_label(width, height))
+            .collect(),
+    }
 }
 
-pub fn write_annotions_to_file(state: &OculanteState) {
+/// The boxes of the label file as it was last read or written
+fn saved_bboxes(state: &OculanteState) -> Vec<AnnoationBoundingBox> {
+    state
+        .saved_annotations
+        .iter()
+        .map(|label| AnnoationBoundingBox::from_label(&label.unnormalise(state.image_dimension)))
+        .collect()
+}
+
+/// Whether the annotations differ from the label file as it was last read or written
+pub fn has_unsaved_edits(state: &OculanteState) -> bool {
+    current_labels(state).labels != state.saved_annotations
+}
+
+/// When a file was last written, if it exists
+pub fn file_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Replace the annotations of the current image with the ones from its label file
+pub fn load_annotations_from_file(state: &mut OculanteState) {
+    state.annotation_bboxes.clear();
+    state.label_conflict = None;
+
+    if state.current_path.is_some() {
+        state.annotation_bboxes = read_annotations(state);
+        state.labels_modified = file_modified(&get_labels_filename(state));
+    }
+    state.saved_annotations = current_labels(state).labels;
+
+    let num_bboxes = state.annotation_bboxes.len();
+    state.selected_bbox_id = state.selected_bbox_id.filter(|id| *id < num_bboxes);
+}
+
+/// Reload the label file of the current image if another program changed it. If there are
+/// unsaved edits, the boxes on disk are kept for the user to choose from instead.
+pub fn check_label_file(state: &mut OculanteState) {
+    if state.current_path.is_none()
+        || !state.is_loaded
+        || state.label_conflict.is_some()
+        || state.bbox_edit_mode.bbox_id().is_some()
+    {
+        return;
+    }
+    if let Some(checked) = state.labels_checked {
+        if checked.elapsed() < LABEL_CHECK_INTERVAL {
+            return;
+        }
+    }
+    state.labels_checked = Some(Instant::now());
+
+    let modified = file_modified(&get_labels_filename(state));
+    if modified == state.labels_modified {
+        return;
+    }
+    if has_unsaved_edits(state) {
+        state.labels_modified = modified;
+        state.label_conflict = Some(read_annotations(state));
+    } else {
+        load_annotations_from_file(state);
+        state.send_message("The labels were changed on disk and have been reloaded");
+    }
+}
+
+/// Lock the labels of the current image for editing, releasing the lock of the previous
+/// image. If someone else holds the lock, the labels are read-only.
+pub fn lock_current_image(state: &mut OculanteState) {
+    let held = state.label_lock.as_ref().map(|lock| lock.image_path());
+    if held != state.current_path.as_deref() {
+        release_current_lock(state);
+    }
+    state.locked_by = None;
+    let p = match (&state.current_path, state.persistent_settings.lock_files) {
+        (Some(p), true) => p.clone(),
+        _ => return,
+    };
+    if state.label_lock.as_ref().map(|lock| lock.is_held()) == Some(true) {
+        return;
+    }
+    state.lock_refreshed = Some(Instant::now());
+    match acquire_lock(&p) {
+        Ok(None) => state.label_lock = Some(HeldLock::new(&p)),
+        Ok(Some(lock)) => {
+            state.label_lock = None;
+            state.locked_by = Some(lock);
+        }
+        Err(e) => state.send_message_err(&format!("Can't lock the labels: {e}")),
+    }
+}
+
+pub fn release_current_lock(state: &mut OculanteState) {
+    // Dropping the lock stops renewing and removes it
+    state.label_lock = None;
+}
+
+/// Take over the lock of the current image once someone else's lock is gone. Our own lock is
+/// renewed in the background by `HeldLock`.
+pub fn refresh_lock(state: &mut OculanteState) {
+    if !state.persistent_settings.lock_files {
+        release_current_lock(state);
+        state.locked_by = None;
+        return;
+    }
+    if state.current_path.is_none() || !state.is_loaded {
+        return;
+    }
+    let lost = state
+        .label_lock
+        .as_ref()
+        .map(|lock| !lock.is_held())
+        .unwrap_or_default();
+    if state.locked_by.is_none() && !lost {
+        return;
+    }
+    if let Some(refreshed) = state.lock_refreshed {
+        if refreshed.elapsed() < LOCK_REFRESH_INTERVAL {
+            return;
+        }
+    }
+    let was_locked = state.locked_by.is_some();
+    lock_current_image(state);
+    if was_locked && state.locked_by.is_none() {
+        // The other annotator may have saved in the meantime
+        load_annotations_from_file(state);
+        state.send_message("The labels of this image can be edited again");
+    }
+}
+
+/// Whether a box is still the box it was, up to the rounding of the label file
+fn unchanged_box(a: &AnnoationBoundingBox, b: &AnnoationBoundingBox) -> bool {
+    a.class_id == b.class_id
+        && (a.x_min() - b.x_min()).abs() < UNCHANGED_BOX_TOLERANCE
+        && (a.x_max() - b.x_max()).abs() < UNCHANGED_BOX_TOLERANCE
+        && (a.y_min() - b.y_min()).abs() < UNCHANGED_BOX_TOLERANCE
+        && (a.y_max() - b.y_max()).abs() < UNCHANGED_BOX_TOLERANCE
+}
+
+/// Merge the boxes another program wrote (`theirs`) with the ones here (`mine`), both edited
+/// from the label file as it was last read or written (`base`). A box of the base changed or
+/// deleted on one side only takes that side's version, so deleted boxes stay deleted. Boxes
+/// added on disk are added here, unless a box of the same class here overlaps them almost
+/// completely.
+pub fn merge_annotations(
+    base: &[AnnoationBoundingBox],
+    mine: &[AnnoationBoundingBox],
+    theirs: &[AnnoationBoundingBox],
+) -> Vec<AnnoationBoundingBox> {
+    let mut keep_mine = vec![true; mine.len()];
+    let mut mine_matched = vec![false; mine.len()];
+    let mut add_theirs = vec![true; theirs.len()];
+    for bbox in base {
+        let in_mine = (0..mine.len()).find(|i| !mine_matched[*i] && unchanged_box(&mine[*i], bbox));
+        let in_theirs =
+            (0..theirs.len()).find(|i| add_theirs[*i] && unchanged_box(&theirs[*i], bbox));
+        if let Some(i) = in_mine {
+            mine_matched[i] = true;
+            // Changed or deleted on disk only
+            keep_mine[i] = in_theirs.is_some();
+        }
+        // Either unchanged on both sides, or changed or deleted here
+        if let Some(i) = in_theirs {
+            add_theirs[i] = false;
+        }
+    }
+
+    let mut merged: Vec<AnnoationBoundingBox> = mine
+        .iter()
+        .zip(&keep_mine)
+        .filter(|(_, keep)| **keep)
+        .map(|(bbox, _)| *bbox)
+        .collect();
+    for (bbox, add) in theirs.iter().zip(&add_theirs) {
+        let known = merged
+            .iter()
+            .any(|b| b.class_id == bbox.class_id && b.iou(*bbox) >= SAME_BOX_IOU);
+        if *add && !known {
+            merged.push(*bbox);
+        }
+    }
+    merged
+}
+
+/// Resolve a conflict with the label file on disk by merging both sides, then save
+pub fn merge_label_conflict(state: &mut OculanteState) {
+    if let Some(theirs) = state.label_conflict.take() {
+        state.annotation_bboxes =
+            merge_annotations(&saved_bboxes(state), &state.annotation_bboxes, &theirs);
+        write_annotions_to_file(state);
+    }
+}
+
+/// The text shown next to an annotation in the image
+pub fn annotation_tag(state: &OculanteState, bbox: &AnnoationBoundingBox) -> String {
+    let mut tag = state.project.class_name(bbox.class_id);
+    if let Some(probability) = bbox.probability {
+        tag.push_str(&format!(" {probability:.2}"));
+    }
+    if let Some(object_id) = bbox.object_id {
+        tag.push_str(&format!(" #{object_id}"));
+    }
+    tag
+}
+
+/// The colour an annotation is drawn with
+pub fn annotation_color(state: &OculanteState, bbox: &AnnoationBoundingBox) -> Color {
+    let index = match (state.persistent_settings.color_by_track, bbox.object_id) {
+        (true, Some(object_id)) => object_id as usize,
+        _ => bbox.class_id as usize,
+    };
+    let mut color = state.label_colors[index % state.label_colors.len()];
+    color.a *= state.persistent_settings.annotation_opacity
+        * state.annotation_visibility.class(bbox.class_id).opacity;
+    color
+}
+
+/// Save the annotations of the current image. Returns false if they could not be written, or
+/// if they have unsaved edits and the label file was changed by another program, as the user
+/// is asked what to do first.
+pub fn write_annotions_to_file(state: &mut OculanteState) -> bool {
+    if let Some(lock) = state.locked_by.clone() {
+        debug!("Not saving labels locked by {} on {}", lock.user, lock.host);
+        if has_unsaved_edits(state) {
+            state.send_message_err(&format!(
+                "The labels are locked by {} on {}, your changes are not saved",
+                lock.user, lock.host
+            ));
+        }
+        return true;
+    }
+    if state.persistent_settings.auto_track_ids {
+        assign_track_ids(state);
+    }
+    if state.label_conflict.is_some() {
+        return false;
+    }
+
     let labels_filename = get_labels_filename(state);
 
-    let mut string_list: Vec<String> = vec![];
-    for label in state.annotation_bboxes.clone() {
-        string_list.push(label.to_yolo_label_str(state.image_dimension.0, state.image_dimension.1));
+    // Don't overwrite what another program wrote since the file was read
+    let modified = file_modified(&labels_filename);
+    if modified != state.labels_modified {
+        if !has_unsaved_edits(state) {
+            debug!(
+                "Keeping {}, which changed on disk",
+                labels_filename.display()
+            );
+            return true;
+        }
+        state.labels_modified = modified;
+        state.label_conflict = Some(read_annotations(state));
+        return false;
     }
 
-    let write_result = std::fs::write(labels_filename.clone(), string_list.join("\n"));
+    let labels = current_labels(state);
+    if let Err(e) = labels.to_file(&labels_filename) {
+        state.send_message_err(&format!(
+            "Can't save the labels to {}: {e}",
+            labels_filename.display()
+        ));
+        return false;
+    }
+    state.labels_modified = file_modified(&labels_filename);
+    if let Some(p) = &state.current_path {
+        state.thumbnails.invalidate(p);
+        state.progress.set_boxes(p, state.annotation_bboxes.len());
+        if let Some(index) = &mut state.folder_index {
+            index.set_labels(p, LabelSummary::new(&labels));
+        }
+    }
+    state.saved_annotations = labels.labels;
 
     let _ = state.message_channel.0.send(Message::Info(format!(
         "Annotation saved to file {}",
         labels_filename.to_str().unwrap()
     )));
+    true
 }
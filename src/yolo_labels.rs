@@ -1,45 +1,92 @@
-use std::path::Path;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 /// YOLO label.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Label {
-    pub label_index: i8,
+    pub label_index: u32,
     pub x_centre: f32,
     pub y_centre: f32,
     pub width: f32,
     pub height: f32,
     pub probability: Option<f32>,
-    pub object_id: Option<i8>,
+    pub object_id: Option<u32>,
+    /// The box was generated by interpolating between two keyframes and has not been reviewed yet
+    pub interpolated: bool,
 }
 
 pub trait Unnormaliser {
     fn unnormalise(&self, dimensions: (u32, u32)) -> Self;
 }
 
-impl From<&str> for Label {
-    fn from(s: &str) -> Self {
-        let split: Vec<&str> = s.split(|x| x == ' ' || x == '\t').collect();
+impl TryFrom<&str> for Label {
+    type Error = anyhow::Error;
 
-        let probability = if split.len() > 5 {
-            Some(split[5].parse().unwrap())
-        } else {
-            None
+    fn try_from(s: &str) -> Result<Self> {
+        let split: Vec<&str> = s.split_whitespace().collect();
+        if split.len() < 5 {
+            bail!("Expected at least 5 columns, found {}", split.len());
+        }
+
+        let probability = match split.get(5) {
+            Some(p) => Some(p.parse().context("Invalid probability")?),
+            None => None,
         };
 
-        let object_id = if split.len() > 6 {
-            Some(split[6].parse().unwrap())
-        } else {
-            None
+        let object_id = match split.get(6) {
+            Some(id) => Some(id.parse().context("Invalid object id")?),
+            None => None,
         };
 
-        Self {
-            label_index: split[0].parse().unwrap(),
-            x_centre: split[1].parse().unwrap(),
-            y_centre: split[2].parse().unwrap(),
-            width: split[3].parse().unwrap(),
-            height: split[4].parse().unwrap(),
+        Ok(Self {
+            label_index: split[0].parse().context("Invalid class")?,
+            x_centre: split[1].parse().context("Invalid x centre")?,
+            y_centre: split[2].parse().context("Invalid y centre")?,
+            width: split[3].parse().context("Invalid width")?,
+            height: split[4].parse().context("Invalid height")?,
             probability,
             object_id,
+            interpolated: false,
+        })
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.label_index, self.x_centre, self.y_centre, self.width, self.height
+        )?;
+
+        // The object id lives in the 7th column, so it can only be written after a probability.
+        // The track file keeps it for the other labels.
+        if let Some(probability) = self.probability {
+            write!(f, " {}", probability)?;
+            if let Some(object_id) = self.object_id {
+                write!(f, " {}", object_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Label {
+    /// Linearly interpolate between two labels. `t` is expected to be in the range 0..=1.
+    pub fn lerp(&self, other: &Label, t: f32) -> Label {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Label {
+            label_index: self.label_index,
+            x_centre: mix(self.x_centre, other.x_centre),
+            y_centre: mix(self.y_centre, other.y_centre),
+            width: mix(self.width, other.width),
+            height: mix(self.height, other.height),
+            probability: None,
+            object_id: self.object_id,
+            interpolated: true,
         }
     }
 }
@@ -56,6 +103,7 @@ impl Unnormaliser for Label {
             height: self.height * height,
             probability: self.probability,
             object_id: self.object_id,
+            interpolated: self.interpolated,
         }
     }
 }
@@ -66,22 +114,98 @@ pub struct Labels {
     pub labels: Vec<Label>,
 }
 
-impl From<&str> for Labels {
-    fn from(s: &str) -> Self {
-        Labels {
-            labels: s
-                .split("\n")
-                .filter(|x| !x.is_empty())
-                .map(Label::from)
-                .collect::<Vec<Label>>(),
-        }
+impl TryFrom<&str> for Labels {
+    type Error = anyhow::Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        let labels = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                Label::try_from(line).with_context(|| format!("Line {}: {line}", i + 1))
+            })
+            .collect::<Result<Vec<Label>>>()?;
+        Ok(Labels { labels })
     }
 }
 
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let string_list = self
+            .labels
+            .iter()
+            .map(|l| l.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{}", string_list.join("\n"))
+    }
+}
+
+/// Track information of a label which the YOLO format has no column for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct TrackInfo {
+    object_id: Option<u32>,
+    interpolated: bool,
+}
+
+/// The file next to a label file holding the track information of its labels, line by line
+pub fn tracks_filename_for(labels_path: &Path) -> PathBuf {
+    labels_path.with_extension("tracks")
+}
+
 impl Labels {
+    /// Read a label file and the track file next to it. Malformed files are an
+    /// `InvalidData` error.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        let string = std::fs::read_to_string(path)?;
-        Ok(Labels::from(string.as_str()))
+        let string = std::fs::read_to_string(&path)?;
+        let mut labels = Labels::try_from(string.as_str())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{e:#}")))?;
+        labels.read_tracks(path.as_ref());
+        Ok(labels)
+    }
+
+    /// Write a label file and the track file next to it
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> {
+        std::fs::write(&path, self.to_string())?;
+        self.write_tracks(path.as_ref())
+    }
+
+    fn read_tracks(&mut self, labels_path: &Path) {
+        let tracks: Vec<TrackInfo> = match std::fs::read_to_string(tracks_filename_for(labels_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+        {
+            Some(tracks) => tracks,
+            None => return,
+        };
+        // The label file was changed by a program which doesn't know about tracks
+        if tracks.len() != self.labels.len() {
+            return;
+        }
+        for (label, track) in self.labels.iter_mut().zip(tracks) {
+            label.object_id = label.object_id.or(track.object_id);
+            label.interpolated = track.interpolated;
+        }
+    }
+
+    /// Write the track file belonging to a label file, or remove it if no label is tracked
+    pub fn write_tracks(&self, labels_path: &Path) -> Result<(), std::io::Error> {
+        let tracks_file = tracks_filename_for(labels_path);
+        let tracks: Vec<TrackInfo> = self
+            .labels
+            .iter()
+            .map(|l| TrackInfo {
+                object_id: l.object_id,
+                interpolated: l.interpolated,
+            })
+            .collect();
+        if tracks.iter().any(|t| *t != TrackInfo::default()) {
+            std::fs::write(tracks_file, serde_json::to_string(&tracks)?)
+        } else if tracks_file.exists() {
+            std::fs::remove_file(tracks_file)
+        } else {
+            Ok(())
+        }
     }
 }
 